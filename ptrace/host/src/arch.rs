@@ -0,0 +1,391 @@
+use crate::{HostError, SyscallResult};
+use nix::{errno::Errno, libc::user_regs_struct};
+use std::{fmt, str::FromStr};
+use syscalls::Sysno;
+
+/// `cs` selector of 32-bit code on x86_64 Linux, `__USER32_CS`.
+const USER32_CS: u64 = 0x23;
+
+/// Instruction set and calling conventions of a tracee. Registers are always accessed through
+/// the x86_64 `user_regs_struct`, the kernel zero extends the registers of 32-bit tasks into it.
+pub trait Arch: fmt::Debug + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Size of a pointer and of a stack slot.
+    fn word_size(&self) -> usize;
+
+    fn syscall_inst(&self) -> &'static [u8];
+
+    fn breakpoint_inst(&self) -> u8 {
+        0xCC
+    }
+
+    fn ip(&self, regs: &user_regs_struct) -> u64 {
+        regs.rip
+    }
+
+    fn set_ip(&self, regs: &mut user_regs_struct, ip: u64) {
+        regs.rip = ip;
+    }
+
+    fn sp(&self, regs: &user_regs_struct) -> u64 {
+        regs.rsp
+    }
+
+    fn set_sp(&self, regs: &mut user_regs_struct, sp: u64) {
+        regs.rsp = sp;
+    }
+
+    /// Frame pointer, `rbp` or `ebp`.
+    fn fp(&self, regs: &user_regs_struct) -> u64 {
+        regs.rbp
+    }
+
+    /// `mov` of the stack pointer into the frame pointer, the second instruction of a
+    /// prologue after `push` of the frame pointer.
+    fn frame_setup_inst(&self) -> &'static [u8];
+
+    /// Load the syscall number and arguments, translated from their x86_64 meaning.
+    fn set_syscall(
+        &self,
+        regs: &mut user_regs_struct,
+        sysno: Sysno,
+        args: [u64; 6],
+    ) -> Result<(), HostError>;
+
+    /// Return value of the syscall that just completed.
+    fn syscall_result(&self, regs: &user_regs_struct) -> SyscallResult;
+
+    /// x86_64 meaning of the syscall number `nr` of this architecture, if it has one.
+    fn sysno(&self, nr: u64) -> Option<Sysno>;
+
+    /// Arguments of the syscall a thread is stopped in, translated like `set_syscall` does.
+    fn syscall_args(&self, regs: &user_regs_struct) -> [u64; 6];
+
+    /// Put the leading function call arguments in registers and return how many were used,
+    /// the rest go on the stack.
+    fn set_call_args(&self, regs: &mut user_regs_struct, args: &[u64]) -> usize;
+
+    fn return_value(&self, regs: &user_regs_struct) -> u64;
+
+    /// General purpose registers with their names, in `info registers` order.
+    fn registers(&self, regs: &user_regs_struct) -> Vec<(&'static str, u64)>;
+}
+
+/// Pick the architecture of a thread from the code segment it runs in.
+pub fn detect(regs: &user_regs_struct) -> &'static dyn Arch {
+    if regs.cs == USER32_CS {
+        &I386
+    } else {
+        &X86_64
+    }
+}
+
+/// 64-bit tracee, `syscall` instruction and System V AMD64 calls.
+#[derive(Debug)]
+pub struct X86_64;
+
+impl Arch for X86_64 {
+    fn name(&self) -> &'static str {
+        "x86_64"
+    }
+
+    fn word_size(&self) -> usize {
+        8
+    }
+
+    fn syscall_inst(&self) -> &'static [u8] {
+        &[0x0F, 0x05]
+    }
+
+    fn frame_setup_inst(&self) -> &'static [u8] {
+        // mov rbp, rsp
+        &[0x48, 0x89, 0xE5]
+    }
+
+    fn set_syscall(
+        &self,
+        regs: &mut user_regs_struct,
+        sysno: Sysno,
+        args: [u64; 6],
+    ) -> Result<(), HostError> {
+        regs.rax = sysno as u64;
+        regs.rdi = args[0];
+        regs.rsi = args[1];
+        regs.rdx = args[2];
+        regs.r10 = args[3];
+        regs.r8 = args[4];
+        regs.r9 = args[5];
+        Ok(())
+    }
+
+    fn syscall_result(&self, regs: &user_regs_struct) -> SyscallResult {
+        SyscallResult::from_raw(regs.rax)
+    }
+
+    fn sysno(&self, nr: u64) -> Option<Sysno> {
+        Sysno::new(nr as usize)
+    }
+
+    fn syscall_args(&self, regs: &user_regs_struct) -> [u64; 6] {
+        [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9]
+    }
+
+    fn set_call_args(&self, regs: &mut user_regs_struct, args: &[u64]) -> usize {
+        let mut arg_regs = [
+            &mut regs.rdi,
+            &mut regs.rsi,
+            &mut regs.rdx,
+            &mut regs.rcx,
+            &mut regs.r8,
+            &mut regs.r9,
+        ];
+        for (reg, arg) in arg_regs.iter_mut().zip(args) {
+            **reg = *arg;
+        }
+        // no vector registers used by variadic callees
+        regs.rax = 0;
+        args.len().min(arg_regs.len())
+    }
+
+    fn return_value(&self, regs: &user_regs_struct) -> u64 {
+        regs.rax
+    }
+
+    fn registers(&self, regs: &user_regs_struct) -> Vec<(&'static str, u64)> {
+        vec![
+            ("rax", regs.rax),
+            ("rbx", regs.rbx),
+            ("rcx", regs.rcx),
+            ("rdx", regs.rdx),
+            ("rsi", regs.rsi),
+            ("rdi", regs.rdi),
+            ("rbp", regs.rbp),
+            ("rsp", regs.rsp),
+            ("r8", regs.r8),
+            ("r9", regs.r9),
+            ("r10", regs.r10),
+            ("r11", regs.r11),
+            ("r12", regs.r12),
+            ("r13", regs.r13),
+            ("r14", regs.r14),
+            ("r15", regs.r15),
+            ("rip", regs.rip),
+            ("eflags", regs.eflags),
+            ("cs", regs.cs),
+            ("ss", regs.ss),
+            ("ds", regs.ds),
+            ("es", regs.es),
+            ("fs", regs.fs),
+            ("gs", regs.gs),
+            ("fs_base", regs.fs_base),
+            ("gs_base", regs.gs_base),
+        ]
+    }
+}
+
+/// 32-bit tracee on a 64-bit kernel, `int 0x80` and cdecl calls with every argument on the
+/// stack.
+#[derive(Debug)]
+pub struct I386;
+
+impl I386 {
+    /// i386 number of an x86_64 syscall, looked up by name. `mmap` becomes `mmap2`, the i386
+    /// `mmap` takes its arguments in memory.
+    fn syscall_number(sysno: Sysno) -> Option<syscalls::x86::Sysno> {
+        match sysno {
+            Sysno::mmap => Some(syscalls::x86::Sysno::mmap2),
+            _ => syscalls::x86::Sysno::from_str(sysno.name()).ok(),
+        }
+    }
+}
+
+impl Arch for I386 {
+    fn name(&self) -> &'static str {
+        "i386"
+    }
+
+    fn word_size(&self) -> usize {
+        4
+    }
+
+    fn syscall_inst(&self) -> &'static [u8] {
+        &[0xCD, 0x80]
+    }
+
+    fn frame_setup_inst(&self) -> &'static [u8] {
+        // mov ebp, esp
+        &[0x89, 0xE5]
+    }
+
+    fn set_syscall(
+        &self,
+        regs: &mut user_regs_struct,
+        sysno: Sysno,
+        mut args: [u64; 6],
+    ) -> Result<(), HostError> {
+        let nr = Self::syscall_number(sysno).ok_or(HostError::UnsupportedSyscall {
+            sysno,
+            arch: self.name(),
+        })?;
+        if sysno == Sysno::mmap {
+            // mmap2 takes the offset in pages, mmap fails the same way on other offsets
+            if !args[5].is_multiple_of(4096) {
+                return Err(HostError::Syscall {
+                    sysno,
+                    errno: Errno::EINVAL,
+                });
+            }
+            args[5] /= 4096;
+        }
+
+        regs.rax = nr.id() as u64;
+        regs.rbx = args[0];
+        regs.rcx = args[1];
+        regs.rdx = args[2];
+        regs.rsi = args[3];
+        regs.rdi = args[4];
+        regs.rbp = args[5];
+        Ok(())
+    }
+
+    fn syscall_result(&self, regs: &user_regs_struct) -> SyscallResult {
+        let eax = regs.rax as u32;
+        match eax as i32 {
+            ret @ -4095..=-1 => SyscallResult(ret as i64),
+            _ => SyscallResult(eax as i64),
+        }
+    }
+
+    fn sysno(&self, nr: u64) -> Option<Sysno> {
+        match syscalls::x86::Sysno::new(nr as usize)? {
+            syscalls::x86::Sysno::mmap2 => Some(Sysno::mmap),
+            sysno => Sysno::from_str(sysno.name()).ok(),
+        }
+    }
+
+    fn syscall_args(&self, regs: &user_regs_struct) -> [u64; 6] {
+        let mut args = [regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp];
+        if regs.orig_rax == syscalls::x86::Sysno::mmap2 as u64 {
+            args[5] *= 4096;
+        }
+        args
+    }
+
+    fn set_call_args(&self, _regs: &mut user_regs_struct, _args: &[u64]) -> usize {
+        0
+    }
+
+    fn return_value(&self, regs: &user_regs_struct) -> u64 {
+        regs.rax as u32 as u64
+    }
+
+    fn registers(&self, regs: &user_regs_struct) -> Vec<(&'static str, u64)> {
+        vec![
+            ("eax", regs.rax as u32 as u64),
+            ("ebx", regs.rbx as u32 as u64),
+            ("ecx", regs.rcx as u32 as u64),
+            ("edx", regs.rdx as u32 as u64),
+            ("esi", regs.rsi as u32 as u64),
+            ("edi", regs.rdi as u32 as u64),
+            ("ebp", regs.rbp as u32 as u64),
+            ("esp", regs.rsp as u32 as u64),
+            ("eip", regs.rip as u32 as u64),
+            ("eflags", regs.eflags),
+            ("cs", regs.cs),
+            ("ss", regs.ss),
+            ("ds", regs.ds),
+            ("es", regs.es),
+            ("fs", regs.fs),
+            ("gs", regs.gs),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zeroed() -> user_regs_struct {
+        // SAFETY: user_regs_struct is plain integers
+        unsafe { std::mem::zeroed() }
+    }
+
+    #[test]
+    fn detect_from_cs() {
+        let mut regs = zeroed();
+        regs.cs = 0x33;
+        assert_eq!(detect(&regs).name(), "x86_64");
+        regs.cs = USER32_CS;
+        assert_eq!(detect(&regs).name(), "i386");
+    }
+
+    #[test]
+    fn i386_syscall() {
+        let mut regs = zeroed();
+        I386.set_syscall(&mut regs, Sysno::getpid, [1, 2, 3, 4, 5, 6])
+            .unwrap();
+        assert_eq!(regs.rax, 20);
+        assert_eq!(
+            [regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp],
+            [1, 2, 3, 4, 5, 6]
+        );
+
+        I386.set_syscall(&mut regs, Sysno::mmap, [0, 4096, 3, 0x22, u64::MAX, 8192])
+            .unwrap();
+        assert_eq!(regs.rax, 192);
+        assert_eq!(regs.rbp, 2);
+        assert!(matches!(
+            I386.set_syscall(&mut regs, Sysno::mmap, [0, 4096, 3, 0x22, u64::MAX, 100]),
+            Err(HostError::Syscall {
+                sysno: Sysno::mmap,
+                errno: Errno::EINVAL
+            })
+        ));
+
+        assert!(matches!(
+            I386.set_syscall(&mut regs, Sysno::newfstatat, [0; 6]),
+            Err(HostError::UnsupportedSyscall { .. })
+        ));
+    }
+
+    #[test]
+    fn i386_syscall_entry() {
+        let mut regs = zeroed();
+        regs.orig_rax = 4;
+        (regs.rbx, regs.rcx, regs.rdx) = (2, 0x1000, 5);
+        assert_eq!(I386.sysno(regs.orig_rax), Some(Sysno::write));
+        assert_eq!(I386.syscall_args(&regs), [2, 0x1000, 5, 0, 0, 0]);
+        assert_eq!(X86_64.sysno(4), Some(Sysno::stat));
+
+        regs.orig_rax = 192;
+        regs.rbp = 2;
+        assert_eq!(I386.sysno(regs.orig_rax), Some(Sysno::mmap));
+        assert_eq!(I386.syscall_args(&regs)[5], 8192);
+
+        // socketcall has no x86_64 counterpart
+        assert_eq!(I386.sysno(102), None);
+        assert_eq!(I386.sysno(u64::MAX), None);
+    }
+
+    #[test]
+    fn i386_result() {
+        let mut regs = zeroed();
+        regs.rax = (-22i32) as u32 as u64;
+        assert_eq!(I386.syscall_result(&regs), SyscallResult(-22));
+        // high addresses of a 32-bit tracee are not negative
+        regs.rax = 0xF7F0_0000;
+        assert_eq!(I386.syscall_result(&regs), SyscallResult(0xF7F0_0000));
+    }
+
+    #[test]
+    fn x86_64_call_args() {
+        let mut regs = zeroed();
+        regs.rax = 1;
+        let used = X86_64.set_call_args(&mut regs, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(used, 6);
+        assert_eq!(regs.rdi, 1);
+        assert_eq!(regs.r9, 6);
+        assert_eq!(regs.rax, 0);
+    }
+}
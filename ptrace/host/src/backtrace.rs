@@ -0,0 +1,319 @@
+use crate::{HostError, Symbols, UProc};
+use gimli::{
+    BaseAddresses, CfaRule, EhFrame, EhFrameHdr, LittleEndian, Register, RegisterRule,
+    UnwindContext, UnwindSection, X86, X86_64,
+};
+use nix::{sys::ptrace, unistd::Pid};
+use object::{Object, ObjectSection};
+use std::fmt;
+
+/// Frames walked at most, in case the chain loops.
+const MAX_FRAMES: usize = 256;
+
+/// `push` of the frame pointer, the first instruction of a prologue.
+const PUSH_FP: u8 = 0x55;
+
+/// `ret`, the last instruction of an epilogue after `pop` of the frame pointer.
+const RET: u8 = 0xC3;
+
+/// `endbr64`, which may come before the prologue.
+const ENDBR64: [u8; 4] = [0xF3, 0x0F, 0x1E, 0xFA];
+
+/// How a frame was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnwindMethod {
+    /// the innermost frame, from the thread's registers
+    Registers,
+    /// from the `.eh_frame` call frame information of the callee
+    Cfi,
+    /// from the frame pointer saved by the callee's prologue
+    FramePointer,
+}
+
+/// One frame of a thread's stack, innermost first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// instruction pointer, for callers the return address
+    pub ip: u64,
+    /// stack pointer of the frame, for callers right above the return address
+    pub sp: u64,
+    /// file name of the module `ip` is in
+    pub module: Option<String>,
+    /// demangled name of the function `ip` is in, for callers the function of the call
+    pub function: Option<String>,
+    /// offset of `ip` from the function, or from the module start without one
+    pub offset: u64,
+    pub method: UnwindMethod,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.module, &self.function) {
+            (Some(module), Some(function)) => write!(
+                f,
+                "{:#x} in {}!{}+{:#x}",
+                self.ip, module, function, self.offset
+            ),
+            (Some(module), None) => write!(f, "{:#x} in {}+{:#x}", self.ip, module, self.offset),
+            _ => write!(f, "{:#x}", self.ip),
+        }
+    }
+}
+
+/// `.eh_frame` of a module, with `.eh_frame_hdr` to search it when there is one. Addresses
+/// are the ones in the file.
+#[derive(Clone)]
+pub(crate) struct Cfi {
+    eh_frame: Vec<u8>,
+    /// empty without one
+    eh_frame_hdr: Vec<u8>,
+    bases: BaseAddresses,
+    address_size: u8,
+}
+
+impl fmt::Debug for Cfi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cfi")
+            .field("eh_frame", &self.eh_frame.len())
+            .field("eh_frame_hdr", &self.eh_frame_hdr.len())
+            .finish()
+    }
+}
+
+/// Registers followed from frame to frame, the others are not needed to find the callers.
+#[derive(Debug, Clone, Copy)]
+struct UnwindRegs {
+    ip: u64,
+    sp: u64,
+    fp: u64,
+}
+
+/// What the call frame information says about the caller of a frame.
+enum CfiStep {
+    Caller(UnwindRegs),
+    /// the return address is undefined, as in `_start`
+    Outermost,
+    /// no information for the address, or rules we do not follow
+    Unknown,
+}
+
+impl Cfi {
+    pub(crate) fn load(elf: &object::File) -> Option<Self> {
+        let eh_frame = elf.section_by_name(".eh_frame")?;
+        let mut bases = BaseAddresses::default().set_eh_frame(eh_frame.address());
+        if let Some(text) = elf.section_by_name(".text") {
+            bases = bases.set_text(text.address());
+        }
+        let hdr = elf.section_by_name(".eh_frame_hdr");
+        if let Some(hdr) = &hdr {
+            bases = bases.set_eh_frame_hdr(hdr.address());
+        }
+        Some(Self {
+            eh_frame: eh_frame.data().ok()?.to_vec(),
+            eh_frame_hdr: hdr
+                .and_then(|hdr| hdr.data().ok())
+                .unwrap_or_default()
+                .to_vec(),
+            bases,
+            address_size: if elf.is_64() { 8 } else { 4 },
+        })
+    }
+
+    /// Registers of the caller of the frame at `addr`, a file address.
+    fn step<F>(
+        &self,
+        addr: u64,
+        regs: UnwindRegs,
+        ctx: &mut UnwindContext<usize>,
+        read_word: F,
+    ) -> CfiStep
+    where
+        F: Fn(u64) -> Option<u64>,
+    {
+        let (sp_reg, fp_reg, ra_reg) = match self.address_size {
+            8 => (X86_64::RSP, X86_64::RBP, X86_64::RA),
+            _ => (X86::ESP, X86::EBP, X86::RA),
+        };
+
+        let mut eh_frame = EhFrame::new(&self.eh_frame, LittleEndian);
+        eh_frame.set_address_size(self.address_size);
+        let hdr = (!self.eh_frame_hdr.is_empty())
+            .then(|| {
+                EhFrameHdr::new(&self.eh_frame_hdr, LittleEndian)
+                    .parse(&self.bases, self.address_size)
+                    .ok()
+            })
+            .flatten();
+        let fde = match hdr.as_ref().and_then(|hdr| hdr.table()) {
+            Some(table) => {
+                table.fde_for_address(&eh_frame, &self.bases, addr, EhFrame::cie_from_offset)
+            }
+            None => eh_frame.fde_for_address(&self.bases, addr, EhFrame::cie_from_offset),
+        };
+        let Some(row) = fde.ok().and_then(|fde| {
+            fde.unwind_info_for_address(&eh_frame, &self.bases, ctx, addr)
+                .ok()
+        }) else {
+            return CfiStep::Unknown;
+        };
+
+        let cfa = match row.cfa() {
+            CfaRule::RegisterAndOffset { register, offset } if *register == sp_reg => {
+                regs.sp.wrapping_add_signed(*offset)
+            }
+            CfaRule::RegisterAndOffset { register, offset } if *register == fp_reg => {
+                regs.fp.wrapping_add_signed(*offset)
+            }
+            _ => return CfiStep::Unknown,
+        };
+        let restore = |register: Register, value: u64| match row.register(register) {
+            // callee saved registers without a rule keep their value
+            RegisterRule::Undefined | RegisterRule::SameValue => Some(value),
+            RegisterRule::Offset(offset) => read_word(cfa.wrapping_add_signed(offset)),
+            RegisterRule::ValOffset(offset) => Some(cfa.wrapping_add_signed(offset)),
+            _ => None,
+        };
+
+        if row.register(ra_reg) == RegisterRule::Undefined {
+            return CfiStep::Outermost;
+        }
+        match (restore(ra_reg, 0), restore(fp_reg, regs.fp)) {
+            (Some(ip), Some(fp)) => CfiStep::Caller(UnwindRegs { ip, sp: cfa, fp }),
+            _ => CfiStep::Unknown,
+        }
+    }
+}
+
+impl UProc {
+    /// Walk the stack of a stopped thread, innermost frame first, and symbolize the frames.
+    /// Each caller is found from the `.eh_frame` call frame information of the module its
+    /// callee is in, or without one by following the saved frame pointer, which code built
+    /// without frame pointers breaks. The walk ends at the outermost frame, as marked by the
+    /// call frame information, or at a return address outside of executable memory.
+    pub fn backtrace(&self, tid: Pid) -> Result<Vec<Frame>, HostError> {
+        self.backtrace_with(tid, &self.symbols()?)
+    }
+
+    /// `backtrace` with `symbols` loaded before, to save loading every module again for each
+    /// backtrace. Code mapped since they were loaded is unwound with frame pointers only, and
+    /// left unsymbolized.
+    pub fn backtrace_with(&self, tid: Pid, symbols: &Symbols) -> Result<Vec<Frame>, HostError> {
+        let arch = self.arch();
+        let word = arch.word_size() as u64;
+        let code: Vec<_> = self
+            .maps()?
+            .into_iter()
+            .filter(|region| region.perms.exec)
+            .collect();
+        let is_code = |addr: u64| code.iter().any(|region| region.contains(addr));
+        let read_word = |addr: u64| -> Option<u64> {
+            let mut buf = [0u8; 8];
+            buf[..word as usize].copy_from_slice(&self.mem_read(addr, word as usize).ok()?);
+            Some(u64::from_le_bytes(buf))
+        };
+
+        let raw = ptrace::getregs(tid)?;
+        let mut regs = UnwindRegs {
+            ip: arch.ip(&raw),
+            sp: arch.sp(&raw),
+            fp: arch.fp(&raw),
+        };
+        let mut frames = vec![symbolize(symbols, regs, false, UnwindMethod::Registers)];
+        let mut ctx = UnwindContext::new();
+
+        while frames.len() < MAX_FRAMES {
+            let caller = frames.len() > 1;
+            // a return address may be right past the end of the calling function
+            let lookup = if caller { regs.ip - 1 } else { regs.ip };
+            let cfi = symbols
+                .modules()
+                .iter()
+                .find(|module| module.contains(lookup))
+                // wraps for modules mapped below their link address, like the bias
+                .and_then(|module| Some((module.cfi()?, lookup.wrapping_sub(module.bias))));
+            let step = match cfi {
+                Some((cfi, addr)) => cfi.step(addr, regs, &mut ctx, read_word),
+                None => CfiStep::Unknown,
+            };
+            let (next, method) = match step {
+                CfiStep::Caller(next) => (next, UnwindMethod::Cfi),
+                CfiStep::Outermost => break,
+                CfiStep::Unknown => match self.frame_pointer_step(regs, caller, symbols, read_word)
+                {
+                    Some(next) => (next, UnwindMethod::FramePointer),
+                    None => break,
+                },
+            };
+            // callers are further up the stack
+            if next.sp <= regs.sp || !is_code(next.ip) {
+                break;
+            }
+            frames.push(symbolize(symbols, next, true, method));
+            regs = next;
+        }
+        Ok(frames)
+    }
+
+    /// Registers of the caller from the frame pointer chain. In the innermost frame, before
+    /// the frame pointer is set up or after it is popped, the caller's frame pointer is still
+    /// in place and the return address is on top of the stack.
+    fn frame_pointer_step<F>(
+        &self,
+        regs: UnwindRegs,
+        caller: bool,
+        symbols: &Symbols,
+        read_word: F,
+    ) -> Option<UnwindRegs>
+    where
+        F: Fn(u64) -> Option<u64>,
+    {
+        let arch = self.arch();
+        let word = arch.word_size() as u64;
+
+        if !caller {
+            let mut inst = self.mem_read(regs.ip, 4).unwrap_or_default();
+            if inst.starts_with(&ENDBR64) {
+                inst = self.mem_read(regs.ip + 4, 4).unwrap_or_default();
+            }
+            let at_entry = symbols
+                .symbolize(regs.ip)
+                .is_some_and(|loc| loc.symbol.is_some() && loc.offset == 0);
+            let ret_slot = match inst.first() {
+                _ if at_entry => Some(regs.sp),
+                Some(&PUSH_FP) | Some(&RET) => Some(regs.sp),
+                _ if inst.starts_with(arch.frame_setup_inst()) => Some(regs.sp + word),
+                _ => None,
+            };
+            if let Some(slot) = ret_slot {
+                return Some(UnwindRegs {
+                    ip: read_word(slot)?,
+                    sp: slot + word,
+                    fp: regs.fp,
+                });
+            }
+        }
+
+        // frames only go up the stack
+        if regs.fp < regs.sp || !regs.fp.is_multiple_of(word) {
+            return None;
+        }
+        Some(UnwindRegs {
+            ip: read_word(regs.fp + word)?,
+            sp: regs.fp + 2 * word,
+            fp: read_word(regs.fp)?,
+        })
+    }
+}
+
+fn symbolize(symbols: &Symbols, regs: UnwindRegs, caller: bool, method: UnwindMethod) -> Frame {
+    let lookup = if caller { regs.ip - 1 } else { regs.ip };
+    let location = symbols.symbolize(lookup);
+    Frame {
+        ip: regs.ip,
+        sp: regs.sp,
+        module: location.map(|loc| loc.module.name().to_string()),
+        function: location.and_then(|loc| loc.symbol.map(|sym| sym.name.clone())),
+        offset: location.map_or(0, |loc| loc.offset + (regs.ip - lookup)),
+        method,
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{thread::Resume, HostError, SignalPolicy, StopReason, UProc, Watchpoint};
+use nix::{
+    errno::Errno,
+    sys::{
+        ptrace,
+        signal::Signal::{self, SIGTRAP},
+    },
+    unistd::Pid,
+};
+
+/// Why `cont` or `step_over` returned, with every thread stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Halt {
+    /// `tid` hit the breakpoint at `addr` and is now the current thread
+    Breakpoint { tid: Pid, addr: u64 },
+    /// `tid` accessed the address of `watchpoint` and is now the current thread, stopped right
+    /// after the access
+    Watchpoint { tid: Pid, watchpoint: Watchpoint },
+    /// `tid` received `signal` and is now the current thread
+    Signal { tid: Pid, signal: Signal },
+    /// `tid` forked the process `child`, which stays stopped until taken with `take_child`
+    Fork { tid: Pid, child: Pid },
+    /// the process executed a new program and its leader is the current thread, every
+    /// breakpoint and watchpoint is gone
+    Exec,
+    /// the current thread finished a step and is at `addr`
+    Stepped { addr: u64 },
+    /// the wait was interrupted by a signal handler of the host
+    Interrupted,
+}
+
+impl UProc {
+    pub fn set_breakpoint(&self, addr: u64) -> Result<(), HostError> {
+        if self.breakpoints.borrow().contains_key(&addr) {
+            return Ok(());
+        }
+
+        let orig = self.mem_read(addr, 1)?[0];
+        self.mem_write(addr, &[self.arch().breakpoint_inst()])?;
+        self.breakpoints.borrow_mut().insert(addr, orig);
+
+        log::debug!("pid: {} breakpoint set at {:#X}", self.pid, addr);
+        Ok(())
+    }
+
+    pub fn remove_breakpoint(&self, addr: u64) -> Result<(), HostError> {
+        let orig = self
+            .breakpoints
+            .borrow_mut()
+            .remove(&addr)
+            .ok_or(HostError::NoBreakpoint(addr))?;
+        self.mem_write(addr, &[orig])?;
+
+        log::debug!("pid: {} breakpoint removed at {:#X}", self.pid, addr);
+        Ok(())
+    }
+
+    pub fn breakpoints(&self) -> Vec<u64> {
+        let mut addrs: Vec<_> = self.breakpoints.borrow().keys().copied().collect();
+        addrs.sort_unstable();
+        addrs
+    }
+
+    pub(crate) fn remove_all_breakpoints(&self) -> Result<(), HostError> {
+        for addr in self.breakpoints() {
+            self.remove_breakpoint(addr)?;
+        }
+        Ok(())
+    }
+
+    /// Resume all threads until one hits a breakpoint, then stop the others, select the thread
+    /// that hit it and return the breakpoint address. `rip` is rewound to the breakpoint, and
+    /// the next resume steps over it.
+    pub fn continue_until_breakpoint(&self) -> Result<u64, HostError> {
+        match self.run(false)? {
+            Halt::Breakpoint { addr, .. } => Ok(addr),
+            halt => unreachable!("{:?} without stopping on signals", halt),
+        }
+    }
+
+    /// Resume all threads like `continue_until_breakpoint`, and also stop them all when one hits a
+    /// watchpoint, forks, execs or receives a signal, which is delivered on the next resume unless
+    /// its policy suppresses it. A signal handler of the host interrupting the wait, like one for
+    /// Ctrl-C, stops the threads too.
+    pub fn cont(&self) -> Result<Halt, HostError> {
+        self.run(true)
+    }
+
+    fn run(&self, stop_on_signals: bool) -> Result<Halt, HostError> {
+        self.step_over_breakpoint()?;
+        self.resume_all()?;
+
+        loop {
+            let stop = match self.wait_any() {
+                Err(HostError::NixError(Errno::EINTR)) if stop_on_signals => {
+                    self.stop_all()?;
+                    return Ok(Halt::Interrupted);
+                }
+                stop => stop?,
+            };
+            let tid = stop.tid;
+            match stop.reason {
+                StopReason::Signal(SIGTRAP) => {
+                    if let Some(addr) = self.rewind_breakpoint(tid)? {
+                        self.stop_all()?;
+                        self.current.set(tid);
+                        log::debug!("tid: {} hit breakpoint at {:#X}", tid, addr);
+                        return Ok(Halt::Breakpoint { tid, addr });
+                    }
+                    self.resume_thread(tid, Some(SIGTRAP), Resume::Continue)?;
+                }
+                StopReason::Watchpoint(watchpoint) if stop_on_signals => {
+                    self.stop_all()?;
+                    self.current.set(tid);
+                    log::debug!("tid: {} hit watchpoint {:?}", tid, watchpoint);
+                    return Ok(Halt::Watchpoint { tid, watchpoint });
+                }
+                StopReason::Fork { child, .. } if stop_on_signals => {
+                    self.stop_all()?;
+                    self.current.set(tid);
+                    return Ok(Halt::Fork { tid, child });
+                }
+                StopReason::Exec { .. } if stop_on_signals => {
+                    self.stop_all()?;
+                    return Ok(Halt::Exec);
+                }
+                StopReason::Signal(signal)
+                    if stop_on_signals && self.signal_policy(signal) == SignalPolicy::Deliver =>
+                {
+                    self.stop_all()?;
+                    self.current.set(tid);
+                    return Ok(Halt::Signal { tid, signal });
+                }
+                _ => self.keep_running(stop)?,
+            }
+        }
+    }
+
+    /// If `tid` trapped right after one of our int3, move `rip` back onto it.
+    pub(crate) fn rewind_breakpoint(&self, tid: Pid) -> Result<Option<u64>, HostError> {
+        let arch = self.arch();
+        let mut regs = ptrace::getregs(tid)?;
+        let addr = arch.ip(&regs).wrapping_sub(1);
+        if !self.breakpoints.borrow().contains_key(&addr) {
+            return Ok(None);
+        }
+
+        arch.set_ip(&mut regs, addr);
+        ptrace::setregs(tid, regs)?;
+        Ok(Some(addr))
+    }
+
+    /// If the current thread is stopped on a breakpoint, execute the original instruction
+    /// with the int3 lifted.
+    pub(crate) fn step_over_breakpoint(&self) -> Result<(), HostError> {
+        let rip = self.arch().ip(&ptrace::getregs(self.current_thread())?);
+        let orig = match self.breakpoints.borrow().get(&rip) {
+            Some(orig) => *orig,
+            None => return Ok(()),
+        };
+
+        self.mem_write(rip, &[orig])?;
+        let stepped = self.sstep();
+        self.mem_write(rip, &[self.arch().breakpoint_inst()])?;
+        stepped
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{patch::Patch, HostError, Stop, StopReason, UProc};
+use nix::sys::{
+    ptrace,
+    signal::Signal::{SIGBUS, SIGFPE, SIGILL, SIGSEGV, SIGTRAP},
+    wait::WaitStatus,
+};
+
+/// Bytes below `rsp` a leaf function may use without adjusting `rsp`.
+const RED_ZONE: u64 = 128;
+
+impl UProc {
+    /// Call the function at `addr` in the current thread following the calling convention of
+    /// the tracee's architecture and return its result. On x86_64 the first six arguments go in
+    /// registers, on i386 none do, and the rest go on the stack, below the red zone. The
+    /// function returns onto an int3 patched over the current `rip`, after which memory and
+    /// every register are restored, also when the call fails. A fault in the function aborts
+    /// the call with `HostError::CallFaulted`, the signal is not delivered. Only the current
+    /// thread runs during the call, so a function waiting on a lock held by another thread
+    /// never returns.
+    pub fn call_function(&self, addr: u64, args: &[u64]) -> Result<u64, HostError> {
+        let tid = self.current_thread();
+        log::trace!(
+            "pid: {} tid: {} call: {:#X}{:X?}",
+            self.pid,
+            tid,
+            addr,
+            args
+        );
+
+        let arch = self.arch();
+        let word = arch.word_size();
+        let mut patch = Patch::new(self, tid)?;
+        let saved = *patch.regs();
+        let ret_addr = arch.ip(&saved);
+
+        let mut regs = patch.injected_regs();
+        let stack_args = &args[arch.set_call_args(&mut regs, args)..];
+
+        // the stack arguments must be 16 byte aligned, right above the return address
+        let mut sp = (arch.sp(&saved) - RED_ZONE) & !0xF;
+        sp = (sp - (stack_args.len() * word) as u64) & !0xF;
+        sp -= word as u64;
+
+        let mut frame = Vec::with_capacity((stack_args.len() + 1) * word);
+        for value in std::iter::once(&ret_addr).chain(stack_args) {
+            frame.extend_from_slice(&value.to_le_bytes()[..word]);
+        }
+        self.mem_write(sp, &frame)?;
+        arch.set_ip(&mut regs, addr);
+        arch.set_sp(&mut regs, sp);
+
+        let orig = self.mem_read(ret_addr, 1)?;
+        patch.write(ret_addr, &[arch.breakpoint_inst()])?;
+        ptrace::setregs(tid, regs)?;
+
+        self.run_until_return(ret_addr, sp + word as u64, &orig)
+    }
+
+    /// Run the current thread until it executes the int3 at `ret_addr` with `rsp` popped to
+    /// `ret_sp`, and return the function's result at that point.
+    fn run_until_return(&self, ret_addr: u64, ret_sp: u64, orig: &[u8]) -> Result<u64, HostError> {
+        let tid = self.current_thread();
+        let arch = self.arch();
+        loop {
+            self.step_over_breakpoint()?;
+            ptrace::cont(tid, None)?;
+            self.set_running(tid, true);
+            let status = self.wait()?;
+            match self.stop_reason(tid, status)? {
+                StopReason::Signal(SIGTRAP) => {
+                    let mut regs = ptrace::getregs(tid)?;
+                    let trapped = arch.ip(&regs) - 1 == ret_addr;
+                    if trapped && arch.sp(&regs) == ret_sp {
+                        return Ok(arch.return_value(&regs));
+                    }
+                    if trapped {
+                        // the callee itself runs the code under the return trap
+                        arch.set_ip(&mut regs, ret_addr);
+                        ptrace::setregs(tid, regs)?;
+                        self.mem_write(ret_addr, orig)?;
+                        let stepped = self.sstep();
+                        self.mem_write(ret_addr, &[arch.breakpoint_inst()])?;
+                        stepped?;
+                        continue;
+                    }
+                    if self.rewind_breakpoint(tid)?.is_none() {
+                        return Err(HostError::UnexpectedWaitStatus(WaitStatus::Stopped(
+                            tid, SIGTRAP,
+                        )));
+                    }
+                }
+                StopReason::Signal(signal @ (SIGSEGV | SIGBUS | SIGILL | SIGFPE)) => {
+                    self.discard_deferred(tid, signal);
+                    let addr = arch.ip(&ptrace::getregs(tid)?);
+                    return Err(HostError::CallFaulted { addr, signal });
+                }
+                reason @ (StopReason::Exited(_) | StopReason::Killed(_)) => {
+                    return Err(HostError::ProcessExited(Stop { tid, reason }))
+                }
+                // other signals are deferred until the thread is resumed
+                reason => log::debug!("tid: {} call interrupted by {:?}", tid, reason),
+            }
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use crate::{HostError, MapRegion, UProc};
+use nix::{libc, unistd::Pid};
+use object::elf;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    mem::size_of,
+    path::Path,
+};
+
+const PAGE: u64 = 4096;
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+/// Largest single read when copying memory into the core.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Regions that cannot be read through `/proc/<pid>/mem`.
+fn is_special(region: &MapRegion) -> bool {
+    matches!(
+        region.path.as_deref(),
+        Some("[vvar]" | "[vvar_vclock]" | "[vsyscall]")
+    )
+}
+
+fn push_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn push_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn push_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+/// `s` truncated or NUL padded to `len` bytes.
+fn push_fixed(buf: &mut Vec<u8>, s: &[u8], len: usize) {
+    let n = s.len().min(len - 1);
+    buf.extend_from_slice(&s[..n]);
+    buf.resize(buf.len() + len - n, 0);
+}
+
+fn pad4(buf: &mut Vec<u8>) {
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+/// One ELF note, in the `CORE` namespace like the ones written by the kernel.
+struct Note {
+    kind: u32,
+    desc: Vec<u8>,
+}
+
+impl Note {
+    fn write(&self, buf: &mut Vec<u8>) {
+        const NAME: &[u8] = b"CORE\0";
+        push_u32(buf, NAME.len() as u32);
+        push_u32(buf, self.desc.len() as u32);
+        push_u32(buf, self.kind);
+        buf.extend_from_slice(NAME);
+        pad4(buf);
+        buf.extend_from_slice(&self.desc);
+        pad4(buf);
+    }
+}
+
+/// Fields after the command name of `/proc/<pid>/stat`, starting with the state.
+fn stat_fields(path: &str) -> Result<Vec<String>, HostError> {
+    let stat = std::fs::read_to_string(path)?;
+    let (_, rest) = stat
+        .rsplit_once(')')
+        .ok_or_else(|| HostError::CoreDump(format!("bad {}", path)))?;
+    Ok(rest.split_whitespace().map(str::to_string).collect())
+}
+
+/// Field of `/proc/<pid>/status` like `SigBlk:` or `Uid:`, first value only.
+fn status_field(status: &str, key: &str) -> Option<String> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(str::to_string)
+}
+
+fn push_timeval(buf: &mut Vec<u8>, ticks: u64) {
+    // SAFETY: sysconf has no preconditions
+    let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    push_u64(buf, ticks / hz);
+    push_u64(buf, ticks % hz * 1_000_000 / hz);
+}
+
+impl UProc {
+    /// `struct elf_prstatus` of one thread.
+    fn prstatus(&self, tid: Pid, stat: &[String]) -> Result<Note, HostError> {
+        let task = format!("/proc/{}/task/{}", self.pid, tid);
+        let task_stat = stat_fields(&format!("{}/stat", task))?;
+        let status = std::fs::read_to_string(format!("{}/status", task))?;
+        let field = |f: &[String], i: usize| f.get(i).and_then(|v| v.parse::<u64>().ok());
+        let sigset = |key| {
+            status_field(&status, key)
+                .and_then(|v| u64::from_str_radix(&v, 16).ok())
+                .unwrap_or(0)
+        };
+        let regs = self.registers_of(tid)?;
+        let raw = regs.raw();
+        // SAFETY: user_regs_struct is 27 u64 without padding, the layout of elf_gregset_t
+        let gregs = unsafe {
+            std::slice::from_raw_parts(
+                raw as *const libc::user_regs_struct as *const u8,
+                size_of::<libc::user_regs_struct>(),
+            )
+        };
+
+        let mut desc = Vec::with_capacity(336);
+        // pr_info, pr_cursig: the threads are stopped
+        push_u32(&mut desc, libc::SIGSTOP as u32);
+        push_u32(&mut desc, 0);
+        push_u32(&mut desc, 0);
+        push_u16(&mut desc, libc::SIGSTOP as u16);
+        push_u16(&mut desc, 0);
+        push_u64(&mut desc, sigset("SigPnd:"));
+        push_u64(&mut desc, sigset("SigBlk:"));
+        push_u32(&mut desc, tid.as_raw() as u32);
+        for i in 1..=3 {
+            // ppid, pgrp, session
+            push_u32(&mut desc, field(stat, i).unwrap_or(0) as u32);
+        }
+        for i in 11..=14 {
+            // utime, stime, cutime, cstime
+            push_timeval(&mut desc, field(&task_stat, i).unwrap_or(0));
+        }
+        desc.extend_from_slice(gregs);
+        // pr_fpvalid
+        push_u32(&mut desc, 1);
+        push_u32(&mut desc, 0);
+        Ok(Note {
+            kind: elf::NT_PRSTATUS,
+            desc,
+        })
+    }
+
+    /// `struct elf_prpsinfo` of the process.
+    fn prpsinfo(&self, stat: &[String]) -> Result<Note, HostError> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.pid))?;
+        let comm = std::fs::read(format!("/proc/{}/comm", self.pid))?;
+        let cmdline = std::fs::read(format!("/proc/{}/cmdline", self.pid))?;
+        let args: Vec<u8> = cmdline
+            .strip_suffix(&[0])
+            .unwrap_or(&cmdline)
+            .iter()
+            .map(|&b| if b == 0 { b' ' } else { b })
+            .collect();
+        let id = |key| {
+            status_field(&status, key)
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(0)
+        };
+        let state = stat[0].as_bytes()[0];
+        let nice = stat.get(16).and_then(|v| v.parse::<i8>().ok()).unwrap_or(0);
+
+        let mut desc = Vec::with_capacity(136);
+        desc.push(b"RSDTZW".iter().position(|&s| s == state).unwrap_or(0) as u8);
+        desc.push(state);
+        desc.push((state == b'Z') as u8);
+        desc.push(nice as u8);
+        push_u32(&mut desc, 0);
+        // pr_flag
+        push_u64(&mut desc, 0);
+        push_u32(&mut desc, id("Uid:"));
+        push_u32(&mut desc, id("Gid:"));
+        push_u32(&mut desc, self.pid.as_raw() as u32);
+        // ppid, pgrp, session
+        for id in stat.iter().skip(1).take(3) {
+            push_u32(&mut desc, id.parse().unwrap_or(0));
+        }
+        push_fixed(&mut desc, comm.trim_ascii_end(), 16);
+        push_fixed(&mut desc, &args, 80);
+        Ok(Note {
+            kind: elf::NT_PRPSINFO,
+            desc,
+        })
+    }
+
+    /// Mapped files: count, page size, then start, end and page offset of each mapping, then
+    /// their NUL-terminated paths.
+    fn file_note(maps: &[MapRegion]) -> Note {
+        let files: Vec<&MapRegion> = maps.iter().filter(|r| r.is_file()).collect();
+        let mut desc = Vec::new();
+        push_u64(&mut desc, files.len() as u64);
+        push_u64(&mut desc, PAGE);
+        for region in &files {
+            push_u64(&mut desc, region.start);
+            push_u64(&mut desc, region.end);
+            push_u64(&mut desc, region.offset / PAGE);
+        }
+        for region in &files {
+            desc.extend_from_slice(region.path.as_deref().unwrap_or_default().as_bytes());
+            desc.push(0);
+        }
+        Note {
+            kind: elf::NT_FILE,
+            desc,
+        }
+    }
+
+    fn core_notes(&self, maps: &[MapRegion]) -> Result<Vec<u8>, HostError> {
+        let stat = stat_fields(&format!("/proc/{}/stat", self.pid))?;
+        let mut notes = vec![self.prpsinfo(&stat)?];
+
+        // the selected thread first, debuggers report it as the one that stopped
+        let current = self.current_thread();
+        let mut tids = self.threads();
+        tids.sort_by_key(|tid| *tid != current);
+        for tid in tids {
+            notes.push(self.prstatus(tid, &stat)?);
+            let fp = self.fp_registers_of(tid)?;
+            // SAFETY: user_fpregs_struct is the 512 bytes fxsave area, without padding
+            let fxsave = unsafe {
+                std::slice::from_raw_parts(
+                    fp.raw() as *const libc::user_fpregs_struct as *const u8,
+                    size_of::<libc::user_fpregs_struct>(),
+                )
+            };
+            notes.push(Note {
+                kind: elf::NT_FPREGSET,
+                desc: fxsave.to_vec(),
+            });
+        }
+
+        notes.push(Note {
+            kind: elf::NT_AUXV,
+            desc: std::fs::read(format!("/proc/{}/auxv", self.pid))?,
+        });
+        notes.push(Self::file_note(maps));
+
+        let mut buf = Vec::new();
+        for note in &notes {
+            note.write(&mut buf);
+        }
+        Ok(buf)
+    }
+
+    /// Copy `region` into `out`, zero filling the parts that cannot be read, with the original
+    /// bytes under the breakpoints.
+    fn write_region<W: Write>(&self, region: &MapRegion, out: &mut W) -> Result<(), HostError> {
+        let mut addr = region.start;
+        while addr < region.end {
+            let len = ((region.end - addr) as usize).min(CHUNK_SIZE);
+            let mut data = self.mem_read(addr, len).unwrap_or_else(|e| {
+                log::debug!("zero filling {:#X}+{:#X}: {}", addr, len, e);
+                vec![0; len]
+            });
+            for (&bp, &orig) in self.breakpoints.borrow().iter() {
+                if let Some(byte) = bp.checked_sub(addr).and_then(|i| data.get_mut(i as usize)) {
+                    *byte = orig;
+                }
+            }
+            out.write_all(&data)?;
+            addr += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Write an ELF core file of the tracee, readable by gdb: the registers of every thread,
+    /// the auxiliary vector, the mapped files and the contents of every readable mapping. The
+    /// threads are stopped first if needed and stay traced.
+    pub fn write_core<W: Write>(&self, out: W) -> Result<(), HostError> {
+        if self.arch().word_size() != 8 {
+            return Err(HostError::CoreDump(format!(
+                "{} tracees are not supported",
+                self.arch().name()
+            )));
+        }
+        if self.any_running() {
+            self.stop_all()?;
+        }
+
+        let maps: Vec<MapRegion> = self
+            .maps()?
+            .into_iter()
+            .filter(|r| !is_special(r))
+            .collect();
+        let notes = self.core_notes(&maps)?;
+
+        let phnum = maps.len() as u64 + 1;
+        let notes_offset = EHDR_SIZE + phnum * PHDR_SIZE;
+        let data_offset = (notes_offset + notes.len() as u64 + PAGE - 1) & !(PAGE - 1);
+
+        let mut head = Vec::with_capacity(data_offset as usize);
+        head.extend_from_slice(&elf::ELFMAG);
+        head.extend_from_slice(&[elf::ELFCLASS64, elf::ELFDATA2LSB, elf::EV_CURRENT]);
+        head.push(elf::ELFOSABI_SYSV);
+        head.resize(16, 0);
+        push_u16(&mut head, elf::ET_CORE);
+        push_u16(&mut head, elf::EM_X86_64);
+        push_u32(&mut head, elf::EV_CURRENT as u32);
+        // entry, phoff, shoff, flags
+        push_u64(&mut head, 0);
+        push_u64(&mut head, EHDR_SIZE);
+        push_u64(&mut head, 0);
+        push_u32(&mut head, 0);
+        push_u16(&mut head, EHDR_SIZE as u16);
+        push_u16(&mut head, PHDR_SIZE as u16);
+        push_u16(&mut head, phnum as u16);
+        // shentsize, shnum, shstrndx
+        push_u16(&mut head, 0);
+        push_u16(&mut head, 0);
+        push_u16(&mut head, 0);
+
+        push_u32(&mut head, elf::PT_NOTE);
+        push_u32(&mut head, 0);
+        push_u64(&mut head, notes_offset);
+        push_u64(&mut head, 0);
+        push_u64(&mut head, 0);
+        push_u64(&mut head, notes.len() as u64);
+        push_u64(&mut head, 0);
+        push_u64(&mut head, 4);
+
+        let mut offset = data_offset;
+        for region in &maps {
+            // unreadable mappings like guard pages keep their place but no contents
+            let filesz = if region.perms.read { region.len() } else { 0 };
+            let flags = [
+                (region.perms.read, elf::PF_R),
+                (region.perms.write, elf::PF_W),
+                (region.perms.exec, elf::PF_X),
+            ]
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(0, |acc, (_, flag)| acc | flag);
+
+            push_u32(&mut head, elf::PT_LOAD);
+            push_u32(&mut head, flags);
+            push_u64(&mut head, offset);
+            push_u64(&mut head, region.start);
+            push_u64(&mut head, 0);
+            push_u64(&mut head, filesz);
+            push_u64(&mut head, region.len());
+            push_u64(&mut head, PAGE);
+            offset += filesz;
+        }
+        head.extend_from_slice(&notes);
+        head.resize(data_offset as usize, 0);
+
+        let mut out = out;
+        out.write_all(&head)?;
+        for region in maps.iter().filter(|r| r.perms.read) {
+            self.write_region(region, &mut out)?;
+        }
+        out.flush()?;
+
+        log::info!(
+            "wrote core of pid: {} with {} segments",
+            self.pid,
+            maps.len()
+        );
+        Ok(())
+    }
+
+    /// Write an ELF core file of the tracee to `path`, see `write_core`.
+    pub fn dump_core<P: AsRef<Path>>(&self, path: P) -> Result<(), HostError> {
+        self.write_core(BufWriter::new(File::create(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_layout() {
+        let mut buf = Vec::new();
+        Note {
+            kind: elf::NT_AUXV,
+            desc: vec![1, 2, 3],
+        }
+        .write(&mut buf);
+        assert_eq!(&buf[..12], [5, 0, 0, 0, 3, 0, 0, 0, 6, 0, 0, 0]);
+        assert_eq!(&buf[12..20], b"CORE\0\0\0\0");
+        assert_eq!(&buf[20..], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn file_note() {
+        let maps: Vec<MapRegion> = [
+            "1000-3000 r-xp 00002000 08:01 42 /bin/victim",
+            "3000-4000 rw-p 00000000 00:00 0 [heap]",
+        ]
+        .iter()
+        .map(|l| l.parse().unwrap())
+        .collect();
+        let desc = UProc::file_note(&maps).desc;
+        let words: Vec<u64> = desc[..40]
+            .chunks(8)
+            .map(|w| u64::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        assert_eq!(words, [1, PAGE, 0x1000, 0x3000, 2]);
+        assert_eq!(&desc[40..], b"/bin/victim\0");
+    }
+}
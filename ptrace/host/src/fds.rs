@@ -0,0 +1,211 @@
+use crate::{HostError, UProc};
+use nix::{
+    errno::Errno,
+    libc::{
+        self, AT_FDCWD, F_DUPFD_CLOEXEC, MAP_ANONYMOUS, MAP_PRIVATE, O_ACCMODE, O_APPEND,
+        O_CLOEXEC, O_CREAT, O_RDONLY, O_WRONLY, PROT_READ, PROT_WRITE,
+    },
+};
+use std::{
+    fmt,
+    io::ErrorKind,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+};
+use syscalls::Sysno;
+
+/// Mode of the files a descriptor is redirected to.
+const REDIRECT_MODE: u64 = 0o644;
+
+/// An open file descriptor of the tracee, from `/proc/<pid>/fd` and `/proc/<pid>/fdinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdInfo {
+    pub fd: i32,
+    /// file the descriptor refers to, or a description like `pipe:[1234]` or
+    /// `anon_inode:[eventfd]`
+    pub path: PathBuf,
+    /// `O_*` flags of the open file
+    pub flags: i32,
+    /// file offset
+    pub pos: u64,
+    /// inode of the socket, for sockets
+    pub socket_inode: Option<u64>,
+}
+
+impl FdInfo {
+    /// `r`, `w` or `rw`.
+    pub fn access(&self) -> &'static str {
+        match self.flags & O_ACCMODE {
+            O_RDONLY => "r",
+            O_WRONLY => "w",
+            _ => "rw",
+        }
+    }
+}
+
+impl fmt::Display for FdInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>4} {:<2} {:>#8o} {:>10} {}",
+            self.fd,
+            self.access(),
+            self.flags,
+            self.pos,
+            self.path.display()
+        )
+    }
+}
+
+/// `pos` and `flags` of an fdinfo file, the flags are in octal.
+fn parse_fdinfo(fdinfo: &str) -> Option<(u64, i32)> {
+    let mut pos = None;
+    let mut flags = None;
+    for line in fdinfo.lines() {
+        match line.split_once(':') {
+            Some(("pos", value)) => pos = value.trim().parse().ok(),
+            Some(("flags", value)) => flags = i32::from_str_radix(value.trim(), 8).ok(),
+            _ => {}
+        }
+    }
+    Some((pos?, flags?))
+}
+
+/// Inode in a `socket:[1234]` link.
+fn socket_inode(path: &Path) -> Option<u64> {
+    path.to_str()?
+        .strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+impl UProc {
+    /// Open file descriptors of the process, by number. Descriptors closed while they are
+    /// listed are left out.
+    pub fn fds(&self) -> Result<Vec<FdInfo>, HostError> {
+        let mut fds = Vec::new();
+        for entry in std::fs::read_dir(format!("/proc/{}/fd", self.pid))? {
+            let Some(fd) = entry?.file_name().to_str().and_then(|s| s.parse().ok()) else {
+                continue;
+            };
+            match self.fd_info(fd) {
+                Ok(info) => fds.push(info),
+                Err(HostError::Io(e)) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        fds.sort_by_key(|info| info.fd);
+        Ok(fds)
+    }
+
+    fn fd_info(&self, fd: i32) -> Result<FdInfo, HostError> {
+        let path = std::fs::read_link(format!("/proc/{}/fd/{}", self.pid, fd))?;
+        let fdinfo = std::fs::read_to_string(format!("/proc/{}/fdinfo/{}", self.pid, fd))?;
+        let (pos, flags) = parse_fdinfo(&fdinfo).ok_or_else(|| {
+            std::io::Error::new(ErrorKind::InvalidData, format!("bad fdinfo `{}`", fdinfo))
+        })?;
+        Ok(FdInfo {
+            fd,
+            socket_inode: socket_inode(&path),
+            path,
+            flags,
+            pos,
+        })
+    }
+
+    /// Duplicate the tracee's descriptor `fd` into this process with `pidfd_getfd`, like a
+    /// descriptor passed over a unix socket. Both share the file offset and status flags, the
+    /// copy is close-on-exec.
+    pub fn dup_fd(&self, fd: i32) -> Result<OwnedFd, HostError> {
+        // SAFETY: pidfd_open takes no pointers
+        let pidfd =
+            Errno::result(unsafe { libc::syscall(libc::SYS_pidfd_open, self.pid.as_raw(), 0) })?;
+        // SAFETY: pidfd_open returned a new descriptor that nothing else owns
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd as i32) };
+        // SAFETY: pidfd_getfd takes no pointers
+        let dup = Errno::result(unsafe {
+            libc::syscall(libc::SYS_pidfd_getfd, pidfd.as_raw_fd(), fd, 0)
+        })?;
+        // SAFETY: pidfd_getfd returned a new descriptor that nothing else owns
+        Ok(unsafe { OwnedFd::from_raw_fd(dup as i32) })
+    }
+
+    /// Point the tracee's descriptor `fd`, like 1 for stdout or 2 for stderr, to the end of
+    /// the file at `path`, created if needed, with `openat`, `dup2` and `close` run in the
+    /// current thread. A relative `path` is relative to the tracee's working directory. Returns
+    /// a tracee descriptor keeping what `fd` referred to before, for `restore_fd`.
+    pub fn redirect_fd<P: AsRef<Path>>(&self, fd: i32, path: P) -> Result<i32, HostError> {
+        let mut name = path.as_ref().as_os_str().as_bytes().to_vec();
+        name.push(0);
+        let mem = self.malloc(
+            0,
+            name.len() as u64,
+            (PROT_READ | PROT_WRITE) as u64,
+            (MAP_PRIVATE | MAP_ANONYMOUS) as u64,
+            u64::MAX,
+            0,
+        )?;
+        mem.write_bytes(0, &name)?;
+
+        let saved = self.syscall(Sysno::fcntl, fd as u64, F_DUPFD_CLOEXEC as u64, 0, 0, 0, 0)?;
+        let flags = O_WRONLY | O_CREAT | O_APPEND | O_CLOEXEC;
+        let opened = self
+            .syscall(
+                Sysno::openat,
+                AT_FDCWD as u64,
+                mem.addr,
+                flags as u64,
+                REDIRECT_MODE,
+                0,
+                0,
+            )
+            .and_then(|file| {
+                let duped = self.syscall(Sysno::dup2, file, fd as u64, 0, 0, 0, 0);
+                // once `fd` is redirected the caller needs `saved` back, a leaked `file` aside
+                if let Err(e) = self.syscall(Sysno::close, file, 0, 0, 0, 0, 0) {
+                    log::warn!("pid: {} cannot close {}: {}", self.pid, file, e);
+                }
+                duped
+            });
+        if let Err(e) = opened {
+            self.syscall(Sysno::close, saved, 0, 0, 0, 0, 0)?;
+            return Err(e);
+        }
+
+        log::info!(
+            "pid: {} fd {} redirected to {:?}, saved as {}",
+            self.pid,
+            fd,
+            path.as_ref(),
+            saved
+        );
+        Ok(saved as i32)
+    }
+
+    /// Undo `redirect_fd`: point `fd` back to what `saved` refers to, and close `saved`.
+    pub fn restore_fd(&self, fd: i32, saved: i32) -> Result<(), HostError> {
+        self.syscall(Sysno::dup2, saved as u64, fd as u64, 0, 0, 0, 0)?;
+        self.syscall(Sysno::close, saved as u64, 0, 0, 0, 0, 0)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fdinfo_fields() {
+        let fdinfo = "pos:\t42\nflags:\t02102001\nmnt_id:\t25\nino:\t1234\n";
+        assert_eq!(parse_fdinfo(fdinfo), Some((42, 0o2102001)));
+        assert_eq!(parse_fdinfo("pos:\t0\n"), None);
+
+        assert_eq!(socket_inode(Path::new("socket:[98765]")), Some(98765));
+        assert_eq!(socket_inode(Path::new("pipe:[98765]")), None);
+        assert_eq!(socket_inode(Path::new("/tmp/socket:[1]")), None);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{HostError, UProc, WATCHPOINT_SLOTS};
+use nix::{sys::wait::WaitStatus, unistd::Pid};
+use std::path::PathBuf;
+
+impl UProc {
+    /// Path of the program the process runs, the new one after an exec.
+    pub fn exe(&self) -> Result<PathBuf, HostError> {
+        Ok(std::fs::read_link(format!("/proc/{}/exe", self.pid))?)
+    }
+
+    /// Take the process forked by one of the threads, reported by `StopReason::Fork`. It is
+    /// traced with the same options and stopped until resumed, and detached when dropped
+    /// even if this process was spawned. A fork child inherits the breakpoints, a vfork
+    /// child shares them with this process until it execs or exits.
+    pub fn take_child(&self, pid: Pid) -> Option<UProc> {
+        let mut children = self.children.borrow_mut();
+        let i = children.iter().position(|child| child.pid == pid)?;
+        Some(children.remove(i))
+    }
+
+    /// Wait for the new process `pid`, which the kernel attached to us, to stop and keep it
+    /// for `take_child`.
+    pub(crate) fn add_child(&self, pid: Pid, vfork: bool) -> Result<(), HostError> {
+        let child = UProc::new(pid, false, self.seized);
+        child.add_thread(pid);
+        match child.wait_tid(pid)? {
+            // SIGSTOP, or PTRACE_EVENT_STOP once seized
+            WaitStatus::Stopped(..) | WaitStatus::PtraceEvent(..) => {}
+            status => return Err(HostError::UnexpectedWaitStatus(status)),
+        }
+        child.arch.set(self.arch());
+        child.options.set(self.options.get());
+        if !vfork {
+            *child.breakpoints.borrow_mut() = self.breakpoints.borrow().clone();
+        }
+        log::debug!("pid: {} new child: {}", self.pid, pid);
+        self.children.borrow_mut().push(child);
+        Ok(())
+    }
+
+    /// Forget what the old program left behind after an exec by `former`, whose thread is now
+    /// the leader. The kernel killed the other threads and cleared the debug registers.
+    pub(crate) fn reset_after_exec(&self, former: Pid) -> Result<(), HostError> {
+        if former != self.pid {
+            let mut threads = self.threads.borrow_mut();
+            if let Some(thread) = threads.remove(&former) {
+                threads.insert(self.pid, thread);
+            }
+        }
+        self.set_running(self.pid, false);
+        self.current.set(self.pid);
+        self.breakpoints.borrow_mut().clear();
+        *self.watchpoints.borrow_mut() = [None; WATCHPOINT_SLOTS];
+        self.detect_arch()?;
+        log::info!("pid: {} exec {:?}", self.pid, self.exe());
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt::Write;
+
+/// Bytes shown per line.
+const LINE: usize = 16;
+
+/// `hexdump -C` style dump of `data` read at `addr`: the address of each line, sixteen bytes
+/// in hex split in two groups of eight, then the printable ones as ASCII.
+pub fn hexdump(addr: u64, data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in data.chunks(LINE).enumerate() {
+        write!(out, "{:016x} ", addr + (i * LINE) as u64).unwrap();
+        for j in 0..LINE {
+            if j % 8 == 0 {
+                out.push(' ');
+            }
+            match chunk.get(j) {
+                Some(b) => write!(out, "{:02x} ", b).unwrap(),
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str(" |");
+        for &b in chunk {
+            out.push(if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            });
+        }
+        out.push_str("|\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_and_partial_lines() {
+        let data: Vec<u8> = b"Hello, world!\n\0\x7fABCD".to_vec();
+        assert_eq!(
+            hexdump(0x1000, &data),
+            "0000000000001000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 7f  |Hello, world!...|\n\
+             0000000000001010  41 42 43 44                                       |ABCD|\n"
+        );
+        assert_eq!(hexdump(0, &[]), "");
+    }
+}
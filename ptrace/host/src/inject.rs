@@ -0,0 +1,53 @@
+use crate::{HostError, UProc};
+use nix::libc::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE, RTLD_NOW};
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+impl UProc {
+    /// Load a shared library into the tracee by calling its `dlopen` from the current thread,
+    /// and return the handle. `path` is resolved by the tracee's dynamic loader, relative to
+    /// its working directory and library search path. On failure the tracee's `dlerror` is
+    /// returned in `HostError::DlOpen`.
+    ///
+    /// The tracee must be past its dynamic loader, and no other thread may hold the loader
+    /// lock since only the current thread runs during the call.
+    pub fn inject_library<P: AsRef<OsStr>>(&self, path: P) -> Result<u64, HostError> {
+        let symbols = self.symbols()?;
+        // glibc 2.34 moved dlopen into libc, older ones only export the internal variant
+        let dlopen = symbols
+            .resolve("dlopen")
+            .or_else(|| symbols.resolve("__libc_dlopen_mode"))
+            .ok_or_else(|| HostError::SymbolNotFound("dlopen".to_string()))?;
+
+        let mut name = path.as_ref().as_bytes().to_vec();
+        name.push(0);
+        let mem = self.malloc(
+            0,
+            name.len() as u64,
+            (PROT_READ | PROT_WRITE) as u64,
+            (MAP_PRIVATE | MAP_ANONYMOUS) as u64,
+            u64::MAX,
+            0,
+        )?;
+        self.mem_write(mem.addr, &name)?;
+
+        let handle = self.call_function(dlopen, &[mem.addr, RTLD_NOW as u64])?;
+        if handle != 0 {
+            log::info!(
+                "pid: {} loaded {:?} handle: {:#X}",
+                self.pid,
+                path.as_ref(),
+                handle
+            );
+            return Ok(handle);
+        }
+
+        let message = match symbols.resolve("dlerror") {
+            Some(dlerror) => match self.call_function(dlerror, &[])? {
+                0 => String::new(),
+                msg => String::from_utf8_lossy(&self.mem_read_cstr(msg, 4096)?).into_owned(),
+            },
+            None => String::new(),
+        };
+        Err(HostError::DlOpen(message))
+    }
+}
@@ -1,17 +1,60 @@
 use nix::{
-    libc::user_regs_struct,
     sys::{
         ptrace,
-        signal::Signal::SIGTRAP,
-        wait::{waitpid, WaitStatus},
+        signal::{self, Signal::SIGKILL, Signal::SIGTRAP},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
     },
     unistd::Pid,
 };
-use std::ffi::c_void;
+use patch::Patch;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap, VecDeque},
+    ffi::OsStr,
+    os::unix::process::CommandExt,
+    process::Command,
+};
 use syscalls::Sysno;
-use sysinfo::{ProcessExt, System, SystemExt};
 use thiserror::Error;
 
+mod arch;
+mod backtrace;
+mod breakpoint;
+mod call;
+mod coredump;
+mod fds;
+mod fork;
+mod hexdump;
+mod inject;
+mod maps;
+mod mem;
+mod patch;
+mod profile;
+mod regs;
+mod scan;
+mod step;
+mod symbols;
+mod syscall;
+mod thread;
+mod trace;
+mod watch;
+
+pub use arch::{Arch, I386, X86_64};
+pub use backtrace::{Frame, UnwindMethod};
+pub use breakpoint::Halt;
+pub use fds::FdInfo;
+pub use hexdump::hexdump;
+pub use maps::{MapRegion, Permissions};
+pub use mem::{Pod, UProcMem};
+pub use profile::Profile;
+pub use regs::{Eflags, FpRegisters, RegisterChange, Registers};
+pub use scan::{Hit, Pattern, ScanFilter, Scanner, ValueScan};
+pub use symbols::{Location, Module, Symbol, Symbols};
+pub use syscall::SyscallResult;
+pub use thread::{SignalPolicy, Stop, StopReason};
+pub use trace::{SyscallArg, SyscallEvent, Syscalls};
+pub use watch::{WatchKind, Watchpoint, WATCHPOINT_SLOTS};
+
 #[derive(Debug, Error)]
 pub enum HostError {
     #[error("Process not found `{0}`")]
@@ -22,27 +65,176 @@ pub enum HostError {
     UnexpectedWaitStatus(WaitStatus),
     #[error("StdIo Error `{0}`")]
     Io(#[from] std::io::Error),
-    #[error("Mmap Error `{0:#?}`")]
-    MmapBadAddress(u64),
-    #[error("Munmap Error `{0:#?}`")]
-    MunmapFailed(u64),
+    #[error("Syscall {sysno} failed with `{errno}`")]
+    Syscall {
+        sysno: Sysno,
+        errno: nix::errno::Errno,
+    },
+    #[error("Unknown syscall `{0}`")]
+    UnknownSyscall(String),
+    #[error("Syscall {sysno} not supported on {arch}")]
+    UnsupportedSyscall { sysno: Sysno, arch: &'static str },
+    #[error("No such register `{0}`")]
+    NoSuchRegister(String),
+    #[error("No breakpoint at `{0:#X}`")]
+    NoBreakpoint(u64),
+    #[error("No free debug register for a watchpoint")]
+    NoFreeWatchpoint,
+    #[error("Cannot watch {len} bytes at `{addr:#X}`")]
+    BadWatchpoint { addr: u64, len: usize },
+    #[error("No watchpoint in debug register {0}")]
+    NoWatchpoint(usize),
+    #[error("No such thread `{0}`")]
+    NoSuchThread(Pid),
+    #[error("Process exited `{0:?}`")]
+    ProcessExited(Stop),
+    #[error("Bad maps line `{0}`")]
+    MapsParse(String),
+    #[error("Unmapped memory at `{addr:#X}` reading or writing {len} bytes")]
+    UnmappedMemory { addr: u64, len: usize },
+    #[error("ELF error in `{0}`: `{1}`")]
+    Elf(String, object::Error),
+    #[error("Symbol not found `{0}`")]
+    SymbolNotFound(String),
+    #[error("dlopen failed `{0}`")]
+    DlOpen(String),
+    #[error("Bad byte pattern `{0}`")]
+    PatternParse(String),
+    #[error("Core dump failed `{0}`")]
+    CoreDump(String),
+    #[error("Injected call at `{addr:#X}` faulted with {signal}")]
+    CallFaulted { addr: u64, signal: signal::Signal },
+    #[error("Out of bounds access of {len} bytes at offset {offset:#X} of {size} bytes")]
+    OutOfBounds { offset: u64, len: usize, size: u64 },
 }
+
+/// Options reporting new threads and processes, execs and exits, set on every tracee.
+const FOLLOW_OPTIONS: ptrace::Options = ptrace::Options::PTRACE_O_TRACECLONE
+    .union(ptrace::Options::PTRACE_O_TRACEFORK)
+    .union(ptrace::Options::PTRACE_O_TRACEVFORK)
+    .union(ptrace::Options::PTRACE_O_TRACEEXEC)
+    .union(ptrace::Options::PTRACE_O_TRACEEXIT);
+
 pub struct UProc {
     pid: Pid,
+    spawned: bool,
+    /// attached with PTRACE_SEIZE, stopped with PTRACE_INTERRUPT instead of SIGSTOP
+    seized: bool,
+    options: Cell<ptrace::Options>,
+    arch: Cell<&'static dyn Arch>,
+    /// original byte under each inserted int3
+    breakpoints: RefCell<HashMap<u64, u8>>,
+    /// watchpoint in each debug register, set in every thread
+    watchpoints: RefCell<[Option<Watchpoint>; WATCHPOINT_SLOTS]>,
+    threads: RefCell<BTreeMap<Pid, thread::Thread>>,
+    current: Cell<Pid>,
+    /// stops reaped by `stop_all` and not yet returned by `wait_any`
+    pending: RefCell<VecDeque<Stop>>,
+    /// forked processes not yet taken with `take_child`
+    children: RefCell<Vec<UProc>>,
+    signal_policy: RefCell<HashMap<signal::Signal, SignalPolicy>>,
 }
 
 impl UProc {
+    fn new(pid: Pid, spawned: bool, seized: bool) -> Self {
+        Self {
+            pid,
+            spawned,
+            seized,
+            options: Cell::new(ptrace::Options::empty()),
+            arch: Cell::new(&X86_64),
+            breakpoints: RefCell::new(HashMap::new()),
+            watchpoints: RefCell::new([None; WATCHPOINT_SLOTS]),
+            threads: RefCell::new(BTreeMap::new()),
+            current: Cell::new(pid),
+            pending: RefCell::new(VecDeque::new()),
+            children: RefCell::new(Vec::new()),
+            signal_policy: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Attach to every thread of a running process and follow the threads it creates. The
+    /// threads are stopped with SIGSTOP, which the process sees as a stop and a SIGCHLD to
+    /// its parent.
     pub fn attach(pid: Pid) -> Result<Self, HostError> {
-        ptrace::attach(pid)?;
+        Self::attach_with(pid, false)
+    }
 
-        log::info!("victim pid: {}", pid);
-        Ok(Self { pid })
+    /// Attach like `attach` with PTRACE_SEIZE, which sends no signal: the threads are stopped
+    /// with PTRACE_INTERRUPT and the process cannot tell it was stopped. Signals sent to it,
+    /// SIGSTOP included, are delivered as usual. Dropping it detaches and lets it run.
+    pub fn seize(pid: Pid) -> Result<Self, HostError> {
+        Self::attach_with(pid, true)
+    }
+
+    fn attach_with(pid: Pid, seized: bool) -> Result<Self, HostError> {
+        let uproc = Self::new(pid, false, seized);
+        uproc.attach_thread(pid)?;
+        uproc.detect_arch()?;
+        uproc.attach_threads()?;
+        uproc.set_options(FOLLOW_OPTIONS)?;
+
+        log::info!("victim pid: {} threads: {:?}", pid, uproc.threads());
+        Ok(uproc)
+    }
+
+    /// Fork and exec `cmd` as a tracee. The returned process is stopped at the exec boundary,
+    /// before the dynamic loader runs, and is killed when dropped.
+    pub fn spawn<C, A, S, E, K, V>(cmd: C, args: A, env: E) -> Result<Self, HostError>
+    where
+        C: AsRef<OsStr>,
+        A: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        E: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        let mut command = Command::new(cmd);
+        command.args(args).envs(env);
+        // SAFETY: PTRACE_TRACEME is a single syscall and does not allocate or take locks
+        unsafe {
+            command.pre_exec(|| ptrace::traceme().map_err(std::io::Error::from));
+        }
+        let child = command.spawn()?;
+
+        let uproc = Self::new(Pid::from_raw(child.id() as i32), true, false);
+        uproc.add_thread(uproc.pid);
+        match uproc.wait()? {
+            WaitStatus::Stopped(_, SIGTRAP) => {}
+            status => return Err(HostError::UnexpectedWaitStatus(status)),
+        }
+        uproc.detect_arch()?;
+        uproc.set_options(ptrace::Options::PTRACE_O_EXITKILL | FOLLOW_OPTIONS)?;
+
+        log::info!("spawned victim pid: {}", uproc.pid);
+        Ok(uproc)
     }
 
     pub fn pid(&self) -> Pid {
         self.pid
     }
 
+    pub fn arch(&self) -> &'static dyn Arch {
+        self.arch.get()
+    }
+
+    fn detect_arch(&self) -> Result<(), HostError> {
+        let arch = arch::detect(&ptrace::getregs(self.pid)?);
+        log::debug!("pid: {} arch: {}", self.pid, arch.name());
+        self.arch.set(arch);
+        Ok(())
+    }
+
+    /// Enable additional ptrace options on top of the ones already set, on every thread.
+    fn set_options(&self, options: ptrace::Options) -> Result<(), HostError> {
+        let options = self.options.get() | options;
+        for tid in self.threads() {
+            ptrace::setoptions(tid, options)?;
+        }
+        self.options.set(options);
+        Ok(())
+    }
+
     fn mem_path(&self) -> String {
         format!("/proc/{}/mem", self.pid.as_raw() as u32)
     }
@@ -52,9 +244,28 @@ impl UProc {
 
         let mut data = vec![0u8; len];
         let mem = std::fs::File::open(self.mem_path())?;
-        let read = mem.read_at(&mut data, addr)?;
+        mem.read_exact_at(&mut data, addr)?;
+
+        Ok(data)
+    }
 
-        data.truncate(read);
+    /// Read a NUL-terminated string of at most `max` bytes, one page at a time so that a
+    /// string ending right before an unmapped page can still be read.
+    pub fn mem_read_cstr(&self, addr: u64, max: usize) -> Result<Vec<u8>, HostError> {
+        const PAGE: u64 = 4096;
+
+        let mut data = Vec::new();
+        let mut cur = addr;
+        while data.len() < max {
+            let chunk = ((PAGE - cur % PAGE) as usize).min(max - data.len());
+            let bytes = self.mem_read(cur, chunk)?;
+            if let Some(nul) = bytes.iter().position(|&b| b == 0) {
+                data.extend_from_slice(&bytes[..nul]);
+                return Ok(data);
+            }
+            data.extend_from_slice(&bytes);
+            cur += chunk as u64;
+        }
         Ok(data)
     }
 
@@ -64,22 +275,37 @@ impl UProc {
             .read(true)
             .write(true)
             .open(self.mem_path())?;
-        let len = mem.write_at(data, addr)?;
-        Ok(len)
+        mem.write_all_at(data, addr)?;
+        Ok(data.len())
     }
 
     fn wait(&self) -> Result<WaitStatus, HostError> {
-        waitpid(self.pid, None).map_err(HostError::NixError)
+        self.wait_tid(self.current_thread())
     }
 
+    /// Execute one instruction of the current thread. Signals arriving first are deferred to
+    /// the next resume and the step is retried.
     fn sstep(&self) -> Result<(), HostError> {
-        ptrace::step(self.pid, None)?;
-        match self.wait()? {
-            WaitStatus::Stopped(_, SIGTRAP) => Ok(()),
-            status => Err(HostError::UnexpectedWaitStatus(status)),
+        let tid = self.current_thread();
+        loop {
+            ptrace::step(tid, None)?;
+            self.set_running(tid, true);
+            let status = self.wait()?;
+            match self.stop_reason(tid, status)? {
+                // a step writing a watched address completes too
+                StopReason::Signal(SIGTRAP) | StopReason::Watchpoint(_) => return Ok(()),
+                reason @ (StopReason::Exited(_) | StopReason::Killed(_)) => {
+                    return Err(HostError::ProcessExited(Stop { tid, reason }))
+                }
+                reason => log::debug!("tid: {} step interrupted by {:?}", tid, reason),
+            }
         }
     }
 
+    /// Run a syscall in the current thread and return its result, or `HostError::Syscall` with
+    /// the decoded errno when it fails. Numbers and arguments follow x86_64, a 32-bit tracee
+    /// gets them translated to its own syscall table and registers.
+    #[allow(clippy::too_many_arguments)]
     pub fn syscall(
         &self,
         syscall: Sysno,
@@ -89,35 +315,44 @@ impl UProc {
         r10: u64,
         r8: u64,
         r9: u64,
-    ) -> Result<user_regs_struct, HostError> {
-        log::trace!("pid: {} syscall: {:#?}", self.pid, syscall);
-        let syscall_inst = [0x0Fu8, 0x05u8];
-
-        let regs = ptrace::getregs(self.pid)?;
-        let ip = regs.rip;
-        let inst = self.mem_read(ip, syscall_inst.len())?;
-        self.mem_write(ip, &syscall_inst)?;
-
-        let new_regs = {
-            let mut new_regs = regs;
-            new_regs.rax = syscall as u64;
-            new_regs.rdi = rdi;
-            new_regs.rsi = rsi;
-            new_regs.rdx = rdx;
-            new_regs.r10 = r10;
-            new_regs.r8 = r8;
-            new_regs.r9 = r9;
-            new_regs
-        };
-        ptrace::setregs(self.pid, new_regs)?;
+    ) -> Result<u64, HostError> {
+        let regs = self.syscall_regs(syscall, rdi, rsi, rdx, r10, r8, r9)?;
+        self.arch().syscall_result(regs.raw()).check(syscall)
+    }
 
-        self.sstep()?;
-        let result = ptrace::getregs(self.pid)?;
+    /// Run a syscall in the current thread and return the registers right after it. A thread
+    /// stopped inside a syscall, like a sleep, restarts it when resumed. An injected syscall
+    /// interrupted by a signal is run again, as the kernel would.
+    #[allow(clippy::too_many_arguments)]
+    pub fn syscall_regs(
+        &self,
+        syscall: Sysno,
+        rdi: u64,
+        rsi: u64,
+        rdx: u64,
+        r10: u64,
+        r8: u64,
+        r9: u64,
+    ) -> Result<Registers, HostError> {
+        let tid = self.current_thread();
+        let arch = self.arch();
+        log::trace!("pid: {} tid: {} syscall: {:#?}", self.pid, tid, syscall);
 
-        self.mem_write(ip, &inst)?;
-        ptrace::setregs(self.pid, regs)?;
+        // restores the instruction and registers when dropped, even on error
+        let mut patch = Patch::new(self, tid)?;
+        let mut regs = patch.injected_regs();
+        arch.set_syscall(&mut regs, syscall, [rdi, rsi, rdx, r10, r8, r9])?;
+        patch.write(arch.ip(&regs), arch.syscall_inst())?;
 
-        Ok(result)
+        loop {
+            ptrace::setregs(tid, regs)?;
+            self.sstep()?;
+            let result = self.registers()?;
+            if !arch.syscall_result(result.raw()).is_restart() {
+                return Ok(result);
+            }
+            log::debug!("tid: {} restarting injected {}", tid, syscall);
+        }
     }
 
     pub fn malloc(
@@ -128,13 +363,8 @@ impl UProc {
         flags: u64,
         fd: u64,
         offset: u64,
-    ) -> Result<UProcMem, HostError> {
+    ) -> Result<UProcMem<'_>, HostError> {
         let mmap = self.syscall(Sysno::mmap, addr, len, prot, flags, fd, offset)?;
-        let mmap = mmap.rax;
-
-        if mmap == 0 {
-            return Err(HostError::MmapBadAddress(addr));
-        }
 
         Ok(UProcMem {
             addr: mmap,
@@ -144,29 +374,67 @@ impl UProc {
     }
 
     fn free(&self, addr: u64, len: u64) -> Result<(), HostError> {
-        let munmap_result = self.syscall(Sysno::munmap, addr, len, 0, 0, 0, 0)?;
-        let munmap_result = munmap_result.rax;
-
-        if munmap_result != 0 {
-            return Err(HostError::MunmapFailed(addr));
-        }
-
+        self.syscall(Sysno::munmap, addr, len, 0, 0, 0, 0)?;
         Ok(())
     }
 }
 
 impl Drop for UProc {
     fn drop(&mut self) {
-        if let Err(e) = ptrace::detach(self.pid, None) {
-            log::error!("failed to detach from pid: {} with err: {:#?}", self.pid, e);
-        } else {
-            log::info!("detach from pid: {}", self.pid);
+        if self.spawned {
+            // ESRCH once it has exited on its own
+            if let Err(e) = signal::kill(self.pid, SIGKILL).or_else(|e| match e {
+                nix::errno::Errno::ESRCH if self.threads().is_empty() => Ok(()),
+                e => Err(e),
+            }) {
+                log::error!("failed to kill pid: {} with err: {:#?}", self.pid, e);
+            }
+            // reap every traced thread, the leader is reported last. Threads killed while
+            // stopped still stop once more on their way out.
+            for tid in self.threads().into_iter().rev() {
+                while let Ok(WaitStatus::PtraceEvent(..)) = waitpid(tid, Some(WaitPidFlag::__WALL))
+                {
+                    let _ = ptrace::cont(tid, None);
+                }
+            }
+            log::info!("killed pid: {}", self.pid);
+            return;
         }
-    }
-}
 
-pub struct UProcMem<'a> {
-    owner: &'a UProc,
-    pub addr: u64,
-    pub len: u64,
+        // runs while unwinding too, after the `Patch` of an interrupted injection restored
+        // the thread it ran in
+        if self.any_running() {
+            if let Err(e) = self.stop_all() {
+                log::error!("failed to stop pid: {} with err: {:#?}", self.pid, e);
+            }
+        }
+        if let Err(e) = self.remove_all_breakpoints() {
+            log::error!(
+                "failed to remove breakpoints from pid: {} with err: {:#?}",
+                self.pid,
+                e
+            );
+        }
+        // a watchpoint left behind would kill it with SIGTRAP
+        if let Err(e) = self.remove_all_watchpoints() {
+            log::error!(
+                "failed to remove watchpoints from pid: {} with err: {:#?}",
+                self.pid,
+                e
+            );
+        }
+        if let Err(e) = self.drain_stop_requests() {
+            log::error!(
+                "failed to drain stops of pid: {} with err: {:#?}",
+                self.pid,
+                e
+            );
+        }
+        for tid in self.threads() {
+            if let Err(e) = ptrace::detach(tid, self.take_deferred(tid)) {
+                log::error!("failed to detach from tid: {} with err: {:#?}", tid, e);
+            }
+        }
+        log::info!("detach from pid: {}", self.pid);
+    }
 }
@@ -1,51 +1,564 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use host::{HostError, UProcMem, UProc};
-use nix::{
-    libc::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE},
-    unistd::Pid,
-};
+mod repl;
+
+use clap::{Args, Parser, Subcommand};
+use host::{hexdump, FdInfo, HostError, MapRegion, SignalPolicy, SyscallResult, UProc};
+use nix::{sys::signal::Signal, unistd::Pid};
+use serde::Serialize;
+use std::{fs::File, path::PathBuf, str::FromStr, time::Duration};
 use syscalls::Sysno;
-use sysinfo::{ProcessExt, System, SystemExt};
+use sysinfo::{PidExt, ProcessExt, System, SystemExt};
 
-fn main() -> Result<(), HostError> {
-    pretty_env_logger::formatted_builder()
-        .filter_level(log::LevelFilter::Trace)
-        .init();
+/// Inspect and poke at running processes.
+#[derive(Debug, Parser)]
+#[command(name = "host")]
+struct Cli {
+    /// Print JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+    /// Log more, repeat for more details
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List processes, optionally only the ones whose name contains FILTER
+    Ps { filter: Option<String> },
+    /// Attach and show where the process is stopped
+    Attach(Target),
+    /// Read LEN bytes at ADDR, an address or a symbol
+    Peek {
+        #[command(flatten)]
+        target: Target,
+        addr: String,
+        #[arg(value_parser = parse_len)]
+        len: usize,
+    },
+    /// Write BYTES, hex like `de ad be ef` or `deadbeef`, at ADDR, an address or a symbol
+    Poke {
+        #[command(flatten)]
+        target: Target,
+        addr: String,
+        #[arg(required = true, num_args = 1..)]
+        bytes: Vec<String>,
+    },
+    /// Show the memory mappings
+    Maps(Target),
+    /// Run a syscall in the process, like `syscall getpid` or `syscall close 3`
+    Syscall {
+        #[command(flatten)]
+        target: Target,
+        syscall: String,
+        #[arg(value_parser = parse_int, allow_negative_numbers = true, num_args = 0..=6)]
+        args: Vec<u64>,
+    },
+    /// Show the registers of the main thread
+    Regs(Target),
+    /// List the open file descriptors
+    Fds(Target),
+    /// Send what the process writes to FD, stdout by default, to the end of PATH
+    Redirect {
+        #[command(flatten)]
+        target: Target,
+        /// Descriptor to redirect, 2 for stderr
+        #[arg(long, default_value_t = 1)]
+        fd: i32,
+        path: PathBuf,
+    },
+    /// Sample the stacks of every thread and print them folded, for flamegraph tools
+    Profile {
+        #[command(flatten)]
+        target: Target,
+        /// Samples per second
+        #[arg(long, default_value_t = 99)]
+        frequency: u32,
+        /// Seconds to sample for, the profile ends earlier when the process exits
+        #[arg(long, default_value = "10", value_parser = parse_secs)]
+        duration: Duration,
+        /// Write the folded stacks to this file instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Debug a running process, or a new one started with `-- CMD ARGS...`, interactively
+    Debug(DebugTarget),
+}
+
+/// Process to attach to, by pid or by name.
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+struct Target {
+    #[arg(long)]
+    pid: Option<i32>,
+    /// Exact process name, the lowest pid wins
+    #[arg(long)]
+    name: Option<String>,
+}
+
+impl Target {
+    /// Attach with PTRACE_SEIZE, the process does not see a SIGSTOP and runs again on exit.
+    fn attach(&self) -> Result<UProc, HostError> {
+        let pid = match (&self.pid, &self.name) {
+            (Some(pid), _) => Pid::from_raw(*pid),
+            (None, Some(name)) => find_by_name(name)?,
+            (None, None) => unreachable!("clap requires a target"),
+        };
+        UProc::seize(pid)
+    }
+}
+
+/// Process to debug, a running one or a command to start.
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+struct DebugTarget {
+    #[arg(long)]
+    pid: Option<i32>,
+    /// Exact process name, the lowest pid wins
+    #[arg(long)]
+    name: Option<String>,
+    /// Command to start stopped at its first instruction, killed when the debugger quits
+    #[arg(last = true)]
+    cmd: Vec<String>,
+}
+
+impl DebugTarget {
+    fn start(self) -> Result<UProc, HostError> {
+        match self.cmd.split_first() {
+            Some((cmd, args)) => {
+                let proc = UProc::spawn(cmd, args, std::env::vars_os())?;
+                // Ctrl-C reaches the whole foreground process group, the debugger stops it
+                proc.set_signal_policy(Signal::SIGINT, SignalPolicy::Suppress);
+                Ok(proc)
+            }
+            None => Target {
+                pid: self.pid,
+                name: self.name,
+            }
+            .attach(),
+        }
+    }
+}
+
+fn find_by_name(name: &str) -> Result<Pid, HostError> {
+    let mut sys = System::new();
+    sys.refresh_processes();
+    let own = std::process::id();
+    sys.processes_by_exact_name(name)
+        .map(|p| p.pid().as_u32())
+        .filter(|pid| *pid != own)
+        .min()
+        .map(|pid| Pid::from_raw(pid as i32))
+        .ok_or_else(|| HostError::ProcessNotFound(name.to_string()))
+}
+
+/// Decimal, or hex with a `0x` prefix. Negative numbers are two's complement, for syscall
+/// arguments like `AT_FDCWD`.
+fn parse_int(s: &str) -> Result<u64, String> {
+    let res = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None if s.starts_with('-') => s.parse::<i64>().map(|v| v as u64),
+        None => s.parse(),
+    };
+    res.map_err(|e| format!("bad number `{}`: {}", s, e))
+}
+
+/// Length of a read, at most what `x` reads in the debugger.
+fn parse_len(s: &str) -> Result<usize, String> {
+    match parse_int(s)? {
+        len if len <= repl::MAX_EXAMINE as u64 => Ok(len as usize),
+        _ => Err(format!("cannot read more than {} bytes", repl::MAX_EXAMINE)),
+    }
+}
+
+/// Seconds, possibly fractional, as a duration.
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s
+        .parse()
+        .map_err(|e| format!("bad duration `{}`: {}", s, e))?;
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("bad duration `{}`: {}", s, e))
+}
+
+/// Number, or the name of a symbol of the process.
+fn address(proc: &UProc, s: &str) -> Result<u64, HostError> {
+    if let Ok(addr) = parse_int(s) {
+        return Ok(addr);
+    }
+    proc.symbols()?
+        .resolve(s)
+        .ok_or_else(|| HostError::SymbolNotFound(s.to_string()))
+}
+
+fn parse_bytes(words: &[String]) -> Result<Vec<u8>, HostError> {
+    let hex: String = words.concat().split_whitespace().collect();
+    let err = || HostError::PatternParse(words.join(" "));
+    // from_str_radix takes a sign, and slicing needs single byte chars
+    if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(err());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| err()))
+        .collect()
+}
+
+fn hex_string(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Serialize)]
+struct ProcessInfo {
+    pid: u32,
+    name: String,
+    cmd: Vec<String>,
+}
 
-    let process_name = "victim";
-    log::info!("host pid: {}", std::process::id());
+#[derive(Serialize)]
+struct AttachInfo {
+    pid: i32,
+    arch: &'static str,
+    threads: Vec<i32>,
+    ip: u64,
+    location: Option<String>,
+}
 
-    let mut sys = System::new_all();
-    sys.refresh_all();
+#[derive(Serialize)]
+struct Peek {
+    addr: u64,
+    len: usize,
+    hex: String,
+}
 
-    let process = sys
-        .processes_by_name(process_name)
-        .take(1)
-        .next()
-        .ok_or_else(|| HostError::ProcessNotFound(process_name.to_string()))?;
+#[derive(Serialize)]
+struct Poke {
+    addr: u64,
+    written: usize,
+}
 
-    let pid = Pid::from_raw(process.pid().into());
+#[derive(Serialize)]
+struct Region {
+    start: u64,
+    end: u64,
+    perms: String,
+    offset: u64,
+    dev: String,
+    inode: u64,
+    path: Option<String>,
+}
 
-    let proc = UProc::attach(pid)?;
-    let umem = proc.malloc(
-        0,
-        8,
-        (PROT_READ | PROT_WRITE) as u64,
-        (MAP_PRIVATE | MAP_ANONYMOUS) as u64,
-        u64::MAX,
-        0,
-    )?;
-    log::info!("mem addr: {:#X}", umem.addr);
+impl From<&MapRegion> for Region {
+    fn from(r: &MapRegion) -> Self {
+        Self {
+            start: r.start,
+            end: r.end,
+            perms: r.perms.to_string(),
+            offset: r.offset,
+            dev: format!("{:02x}:{:02x}", r.dev.0, r.dev.1),
+            inode: r.inode,
+            path: r.path.clone(),
+        }
+    }
+}
 
-    let read = proc.mem_read(umem.addr, umem.len as usize);
-    log::info!("malloc: {:?}", read);
+#[derive(Serialize)]
+struct Fd {
+    fd: i32,
+    path: String,
+    access: &'static str,
+    flags: i32,
+    pos: u64,
+    socket_inode: Option<u64>,
+}
 
-    let _res = proc.syscall(Sysno::time, umem.addr, 0, 0, 0, 0, 0)?;
-    let read = proc.mem_read(umem.addr, umem.len as usize)?;
+impl From<&FdInfo> for Fd {
+    fn from(info: &FdInfo) -> Self {
+        Self {
+            fd: info.fd,
+            path: info.path.to_string_lossy().into_owned(),
+            access: info.access(),
+            flags: info.flags,
+            pos: info.pos,
+            socket_inode: info.socket_inode,
+        }
+    }
+}
 
-    let mut read = std::io::Cursor::new(read);
-    let output =  read.read_i64::<LittleEndian>()?;
+#[derive(Serialize)]
+struct Redirect {
+    fd: i32,
+    path: PathBuf,
+    /// descriptor of the process keeping the previous target of `fd`
+    saved: i32,
+}
+
+#[derive(Serialize)]
+struct SyscallOutput {
+    syscall: String,
+    args: Vec<u64>,
+    ret: i64,
+    errno: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Stack {
+    /// thread name, then outermost to innermost frame
+    frames: Vec<String>,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct ProfileOutput {
+    samples: u64,
+    stacks: Vec<Stack>,
+}
 
-    log::info!("Time Output: {:?}", output);
+fn print_json<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("serializable output")
+    );
+}
+
+fn run(cli: Cli) -> Result<(), HostError> {
+    let json = cli.json;
+    match cli.command {
+        Command::Ps { filter } => {
+            let mut sys = System::new();
+            sys.refresh_processes();
+            let mut procs: Vec<ProcessInfo> = sys
+                .processes()
+                .values()
+                .filter(|p| filter.as_deref().is_none_or(|f| p.name().contains(f)))
+                .map(|p| ProcessInfo {
+                    pid: p.pid().as_u32(),
+                    name: p.name().to_string(),
+                    cmd: p.cmd().to_vec(),
+                })
+                .collect();
+            procs.sort_by_key(|p| p.pid);
+            if json {
+                print_json(&procs);
+            } else {
+                for p in procs {
+                    println!("{:>7} {:<16} {}", p.pid, p.name, p.cmd.join(" "));
+                }
+            }
+        }
+        Command::Attach(target) => {
+            let proc = target.attach()?;
+            let ip = proc.registers()?.ip();
+            let location = proc.symbols()?.symbolize(ip).map(|l| l.to_string());
+            let info = AttachInfo {
+                pid: proc.pid().as_raw(),
+                arch: proc.arch().name(),
+                threads: proc.threads().iter().map(|t| t.as_raw()).collect(),
+                ip,
+                location,
+            };
+            if json {
+                print_json(&info);
+            } else {
+                println!("pid {} {}", info.pid, info.arch);
+                println!("threads {:?}", info.threads);
+                match info.location {
+                    Some(location) => println!("stopped at {:#x} in {}", ip, location),
+                    None => println!("stopped at {:#x}", ip),
+                }
+            }
+        }
+        Command::Peek { target, addr, len } => {
+            let proc = target.attach()?;
+            let addr = address(&proc, &addr)?;
+            let data = proc.mem_read(addr, len)?;
+            if json {
+                print_json(&Peek {
+                    addr,
+                    len: data.len(),
+                    hex: hex_string(&data),
+                });
+            } else {
+                print!("{}", hexdump(addr, &data));
+            }
+        }
+        Command::Poke {
+            target,
+            addr,
+            bytes,
+        } => {
+            let proc = target.attach()?;
+            let addr = address(&proc, &addr)?;
+            let written = proc.mem_write(addr, &parse_bytes(&bytes)?)?;
+            if json {
+                print_json(&Poke { addr, written });
+            } else {
+                println!("wrote {} bytes at {:#x}", written, addr);
+            }
+        }
+        Command::Maps(target) => {
+            let proc = target.attach()?;
+            let maps = proc.maps()?;
+            if json {
+                print_json(&maps.iter().map(Region::from).collect::<Vec<_>>());
+            } else {
+                for region in maps {
+                    println!("{}", region);
+                }
+            }
+        }
+        Command::Fds(target) => {
+            let proc = target.attach()?;
+            let fds = proc.fds()?;
+            if json {
+                print_json(&fds.iter().map(Fd::from).collect::<Vec<_>>());
+            } else {
+                for info in fds {
+                    println!("{}", info);
+                }
+            }
+        }
+        Command::Redirect { target, fd, path } => {
+            // the process resolves relative paths from its own working directory
+            let path = std::path::absolute(path)?;
+            let proc = target.attach()?;
+            let saved = proc.redirect_fd(fd, &path)?;
+            if json {
+                print_json(&Redirect { fd, path, saved });
+            } else {
+                println!(
+                    "fd {} now writes to {}, previous target kept as fd {}",
+                    fd,
+                    path.display(),
+                    saved
+                );
+            }
+        }
+        Command::Syscall {
+            target,
+            syscall,
+            args,
+        } => {
+            let sysno = Sysno::from_str(&syscall)
+                .map_err(|_| HostError::UnknownSyscall(syscall.clone()))?;
+            let mut regs_args = [0u64; 6];
+            for (slot, arg) in regs_args.iter_mut().zip(&args) {
+                *slot = *arg;
+            }
+            let [a0, a1, a2, a3, a4, a5] = regs_args;
+            let proc = target.attach()?;
+            let regs = proc.syscall_regs(sysno, a0, a1, a2, a3, a4, a5)?;
+            let ret: SyscallResult = proc.arch().syscall_result(regs.raw());
+            if json {
+                print_json(&SyscallOutput {
+                    syscall: sysno.name().to_string(),
+                    args,
+                    ret: ret.0,
+                    errno: ret.errno().map(|e| format!("{:?}", e)),
+                });
+            } else {
+                let args: Vec<String> = args.iter().map(|a| format!("{:#x}", a)).collect();
+                println!("{}({}) = {}", sysno.name(), args.join(", "), ret);
+            }
+        }
+        Command::Profile {
+            target,
+            frequency,
+            duration,
+            output,
+        } => {
+            let proc = target.attach()?;
+            let profile = proc.profile(frequency, duration)?;
+            if json {
+                let stacks: Vec<Stack> = profile
+                    .stacks()
+                    .map(|(frames, count)| Stack {
+                        frames: frames.to_vec(),
+                        count,
+                    })
+                    .collect();
+                print_json(&ProfileOutput {
+                    samples: profile.samples(),
+                    stacks,
+                });
+            } else {
+                match output {
+                    Some(path) => profile.write_folded(File::create(path)?)?,
+                    None => profile.write_folded(std::io::stdout().lock())?,
+                }
+                eprintln!("{} samples", profile.samples());
+            }
+        }
+        Command::Debug(target) => {
+            let proc = target.start()?;
+            repl::run(proc).map_err(std::io::Error::other)?;
+        }
+        Command::Regs(target) => {
+            let proc = target.attach()?;
+            let regs = proc.registers()?;
+            if json {
+                let map: serde_json::Map<String, serde_json::Value> = regs
+                    .iter()
+                    .map(|(name, v)| (name.to_string(), v.into()))
+                    .collect();
+                print_json(&map);
+            } else {
+                print!("{}", regs);
+            }
+        }
+    }
     Ok(())
 }
+
+fn main() {
+    let cli = Cli::parse();
+    let level = match cli.verbose {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    pretty_env_logger::formatted_builder()
+        .filter_level(level)
+        .init();
+
+    if let Err(e) = run(cli) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_definition() {
+        Cli::command().debug_assert();
+        let syscall = [
+            "host", "syscall", "--pid", "1", "mmap", "0", "1", "2", "3", "4",
+        ];
+        assert!(Cli::try_parse_from(syscall.iter().chain(&["-1"])).is_ok());
+        assert!(Cli::try_parse_from(syscall.iter().chain(&["-1", "7"])).is_err());
+    }
+
+    #[test]
+    fn numbers_and_bytes() {
+        assert_eq!(parse_int("0x10"), Ok(16));
+        assert_eq!(parse_int("-100"), Ok(-100i64 as u64));
+        assert!(parse_int("zz").is_err());
+
+        let words = ["de ad".to_string(), "beef".to_string()];
+        assert_eq!(parse_bytes(&words).unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+        assert!(parse_bytes(&["abc".to_string()]).is_err());
+        assert!(parse_bytes(&["aéb".to_string()]).is_err());
+        assert!(parse_bytes(&["+f".to_string()]).is_err());
+
+        assert_eq!(parse_len("0x10000"), Ok(0x10000));
+        assert!(parse_len("0xffffffffffff").is_err());
+
+        assert_eq!(parse_secs("0.5"), Ok(Duration::from_millis(500)));
+        for bad in ["-1", "nan", "inf", "soon"] {
+            assert!(parse_secs(bad).is_err(), "{}", bad);
+        }
+    }
+}
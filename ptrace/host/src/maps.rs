@@ -0,0 +1,250 @@
+use crate::{HostError, UProc};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+    /// `s` shared or `p` private copy-on-write
+    pub shared: bool,
+}
+
+impl FromStr for Permissions {
+    type Err = HostError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let b = s.as_bytes();
+        if b.len() != 4 {
+            return Err(HostError::MapsParse(s.to_string()));
+        }
+        Ok(Self {
+            read: b[0] == b'r',
+            write: b[1] == b'w',
+            exec: b[2] == b'x',
+            shared: b[3] == b's',
+        })
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}{}",
+            if self.read { 'r' } else { '-' },
+            if self.write { 'w' } else { '-' },
+            if self.exec { 'x' } else { '-' },
+            if self.shared { 's' } else { 'p' },
+        )
+    }
+}
+
+/// One line of `/proc/<pid>/maps`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapRegion {
+    pub start: u64,
+    pub end: u64,
+    pub perms: Permissions,
+    pub offset: u64,
+    /// major, minor
+    pub dev: (u32, u32),
+    pub inode: u64,
+    /// file path or pseudo path like `[heap]`, `None` for anonymous mappings
+    pub path: Option<String>,
+}
+
+impl MapRegion {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Backed by a file, as opposed to anonymous memory or `[heap]`, `[stack]`, ...
+    pub fn is_file(&self) -> bool {
+        self.inode != 0 && self.path.as_deref().is_some_and(|p| p.starts_with('/'))
+    }
+}
+
+impl FromStr for MapRegion {
+    type Err = HostError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let err = || HostError::MapsParse(line.to_string());
+        let hex = |s: &str| u64::from_str_radix(s, 16).map_err(|_| err());
+
+        let mut fields = line.splitn(6, ' ');
+        let mut next = || fields.next().ok_or_else(err);
+        let (start, end) = next()?.split_once('-').ok_or_else(err)?;
+        let perms = next()?.parse()?;
+        let offset = hex(next()?)?;
+        let (major, minor) = next()?.split_once(':').ok_or_else(err)?;
+        let inode = next()?.parse().map_err(|_| err())?;
+        let path = fields.next().map(str::trim_start).filter(|p| !p.is_empty());
+
+        Ok(Self {
+            start: hex(start)?,
+            end: hex(end)?,
+            perms,
+            offset,
+            dev: (hex(major)? as u32, hex(minor)? as u32),
+            inode,
+            path: path.map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for MapRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:016x}-{:016x} {} {:08x} {:02x}:{:02x} {:<10} {}",
+            self.start,
+            self.end,
+            self.perms,
+            self.offset,
+            self.dev.0,
+            self.dev.1,
+            self.inode,
+            self.path.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/// Check that `[addr, addr + len)` is covered by `regions` without gaps.
+pub(crate) fn check_mapped(regions: &[MapRegion], addr: u64, len: usize) -> Result<(), HostError> {
+    let unmapped = |at| HostError::UnmappedMemory { addr: at, len };
+    let end = addr.checked_add(len as u64).ok_or(unmapped(addr))?;
+
+    let mut cur = addr;
+    for region in regions {
+        if cur >= end {
+            break;
+        }
+        if region.end <= cur {
+            continue;
+        }
+        if region.start > cur {
+            break;
+        }
+        cur = region.end;
+    }
+    if cur < end {
+        return Err(unmapped(cur));
+    }
+    Ok(())
+}
+
+impl UProc {
+    pub fn maps(&self) -> Result<Vec<MapRegion>, HostError> {
+        std::fs::read_to_string(format!("/proc/{}/maps", self.pid))?
+            .lines()
+            .map(str::parse)
+            .collect()
+    }
+
+    /// Like `mem_read`, but fails with `UnmappedMemory` instead of an io error when the range
+    /// is not entirely mapped.
+    pub fn mem_read_mapped(&self, addr: u64, len: usize) -> Result<Vec<u8>, HostError> {
+        check_mapped(&self.maps()?, addr, len)?;
+        self.mem_read(addr, len)
+    }
+
+    /// Like `mem_write`, but fails with `UnmappedMemory` instead of an io error when the range
+    /// is not entirely mapped.
+    pub fn mem_write_mapped(&self, addr: u64, data: &[u8]) -> Result<usize, HostError> {
+        check_mapped(&self.maps()?, addr, data.len())?;
+        self.mem_write(addr, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_file() {
+        let line = "55d0c0a00000-55d0c0a21000 r-xp 00005000 fd:01 1835327                    /usr/bin/victim";
+        let region: MapRegion = line.parse().unwrap();
+        assert_eq!(region.start, 0x55d0c0a00000);
+        assert_eq!(region.len(), 0x21000);
+        assert_eq!(
+            region.perms,
+            Permissions {
+                read: true,
+                write: false,
+                exec: true,
+                shared: false
+            }
+        );
+        assert_eq!(region.offset, 0x5000);
+        assert_eq!(region.dev, (0xfd, 0x01));
+        assert_eq!(region.inode, 1835327);
+        assert_eq!(region.path.as_deref(), Some("/usr/bin/victim"));
+        assert!(region.is_file());
+        assert_eq!(region.perms.to_string(), "r-xp");
+    }
+
+    #[test]
+    fn parse_anonymous_and_pseudo() {
+        let anon: MapRegion = "7f0000000000-7f0000001000 rw-s 00000000 00:00 0 "
+            .parse()
+            .unwrap();
+        assert_eq!(anon.path, None);
+        assert!(anon.perms.shared);
+        assert!(!anon.is_file());
+
+        let stack: MapRegion =
+            "7ffc00000000-7ffc00021000 rw-p 00000000 00:00 0                          [stack]"
+                .parse()
+                .unwrap();
+        assert_eq!(stack.path.as_deref(), Some("[stack]"));
+        assert!(!stack.is_file());
+    }
+
+    #[test]
+    fn parse_path_with_spaces() {
+        let region: MapRegion = "1000-2000 r--p 00000000 08:01 42   /tmp/a b (deleted)"
+            .parse()
+            .unwrap();
+        assert_eq!(region.path.as_deref(), Some("/tmp/a b (deleted)"));
+    }
+
+    #[test]
+    fn parse_garbage() {
+        assert!(matches!(
+            "not a map".parse::<MapRegion>(),
+            Err(HostError::MapsParse(_))
+        ));
+    }
+
+    #[test]
+    fn mapped_ranges() {
+        let regions: Vec<MapRegion> = [
+            "1000-2000 r--p 00000000 00:00 0",
+            "2000-3000 rw-p 00000000 00:00 0",
+            "5000-6000 rw-p 00000000 00:00 0",
+        ]
+        .iter()
+        .map(|l| l.parse().unwrap())
+        .collect();
+
+        assert!(check_mapped(&regions, 0x1800, 0x1000).is_ok());
+        assert!(check_mapped(&regions, 0x5000, 0x1000).is_ok());
+        assert!(matches!(
+            check_mapped(&regions, 0x2800, 0x1000),
+            Err(HostError::UnmappedMemory { addr: 0x3000, .. })
+        ));
+        assert!(matches!(
+            check_mapped(&regions, 0x800, 0x10),
+            Err(HostError::UnmappedMemory { addr: 0x800, .. })
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{HostError, UProc};
+use std::mem::size_of;
+
+/// Plain data that can be copied to and from tracee memory as raw bytes.
+///
+/// # Safety
+///
+/// Implementors must have no padding and be valid for any bit pattern.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(unsafe impl Pod for $t {})*
+    };
+}
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+pub(crate) fn bytes_of<T: Pod>(value: &T) -> &[u8] {
+    // SAFETY: Pod has no padding, every byte of value is initialized
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+pub(crate) fn from_bytes<T: Pod>(bytes: &[u8]) -> T {
+    assert_eq!(bytes.len(), size_of::<T>());
+    // SAFETY: Pod is valid for any bit pattern, the length is checked above
+    unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+impl UProc {
+    pub fn read_value<T: Pod>(&self, addr: u64) -> Result<T, HostError> {
+        Ok(from_bytes(&self.mem_read(addr, size_of::<T>())?))
+    }
+
+    pub fn write_value<T: Pod>(&self, addr: u64, value: &T) -> Result<(), HostError> {
+        self.mem_write(addr, bytes_of(value))?;
+        Ok(())
+    }
+}
+
+/// Memory mapped in the tracee by `UProc::malloc`, unmapped again when dropped.
+/// Offsets of every accessor are relative to `addr` and checked against `len`.
+pub struct UProcMem<'a> {
+    pub(crate) owner: &'a UProc,
+    pub addr: u64,
+    pub len: u64,
+}
+
+impl<'a> UProcMem<'a> {
+    fn check(&self, offset: u64, len: usize) -> Result<u64, HostError> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.len => Ok(self.addr + offset),
+            _ => Err(HostError::OutOfBounds {
+                offset,
+                len,
+                size: self.len,
+            }),
+        }
+    }
+
+    pub fn read_bytes(&self, offset: u64, len: usize) -> Result<Vec<u8>, HostError> {
+        self.owner.mem_read(self.check(offset, len)?, len)
+    }
+
+    pub fn write_bytes(&self, offset: u64, data: &[u8]) -> Result<(), HostError> {
+        self.owner
+            .mem_write(self.check(offset, data.len())?, data)?;
+        Ok(())
+    }
+
+    pub fn read_value<T: Pod>(&self, offset: u64) -> Result<T, HostError> {
+        self.owner.read_value(self.check(offset, size_of::<T>())?)
+    }
+
+    pub fn write_value<T: Pod>(&self, offset: u64, value: &T) -> Result<(), HostError> {
+        self.owner
+            .write_value(self.check(offset, size_of::<T>())?, value)
+    }
+
+    /// Copy of the whole allocation.
+    pub fn to_vec(&self) -> Result<Vec<u8>, HostError> {
+        self.read_bytes(0, self.len as usize)
+    }
+
+    /// Keep the mapping in the tracee and return its address.
+    pub fn leak(self) -> u64 {
+        let addr = self.addr;
+        std::mem::forget(self);
+        addr
+    }
+}
+
+impl<'a> Drop for UProcMem<'a> {
+    fn drop(&mut self) {
+        if let Err(e) = self.owner.free(self.addr, self.len) {
+            log::error!(
+                "failed to unmap {:#X} from pid: {} with err: {:#?}",
+                self.addr,
+                self.owner.pid,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pod_roundtrip() {
+        let value = [0x1122_3344_5566_7788u64, 42];
+        let bytes = bytes_of(&value).to_vec();
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(bytes[0], 0x88);
+        assert_eq!(from_bytes::<[u64; 2]>(&bytes), value);
+        assert_eq!(from_bytes::<f64>(bytes_of(&1.5f64)), 1.5);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{HostError, UProc};
+use nix::{libc::user_regs_struct, sys::ptrace, unistd::Pid};
+
+/// Code and registers of a thread changed to run injected code. Dropping it writes the
+/// original bytes back and restores the registers, whether the injection succeeded, failed or
+/// panicked, so the tracee never resumes in the middle of it.
+pub(crate) struct Patch<'a> {
+    owner: &'a UProc,
+    tid: Pid,
+    regs: user_regs_struct,
+    /// original bytes of each write, restored in reverse order
+    saved: Vec<(u64, Vec<u8>)>,
+}
+
+impl<'a> Patch<'a> {
+    pub(crate) fn new(owner: &'a UProc, tid: Pid) -> Result<Self, HostError> {
+        Ok(Self {
+            owner,
+            tid,
+            regs: ptrace::getregs(tid)?,
+            saved: Vec::new(),
+        })
+    }
+
+    /// Registers of the thread before the injection.
+    pub(crate) fn regs(&self) -> &user_regs_struct {
+        &self.regs
+    }
+
+    /// Registers to start the injected code from. `orig_rax` is -1 so that the kernel does not
+    /// take them for a syscall interrupted with an ERESTART* code and rewind `rip` to restart
+    /// it. The thread's own interrupted syscall comes back with the saved registers, and is
+    /// restarted on the next resume.
+    pub(crate) fn injected_regs(&self) -> user_regs_struct {
+        let mut regs = self.regs;
+        regs.orig_rax = u64::MAX;
+        regs
+    }
+
+    pub(crate) fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), HostError> {
+        let orig = self.owner.mem_read(addr, data.len())?;
+        self.owner.mem_write(addr, data)?;
+        self.saved.push((addr, orig));
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), HostError> {
+        // a panic may leave the thread running the injected code
+        if self.owner.is_running(self.tid) {
+            self.owner.stop_all()?;
+        }
+        while let Some((addr, orig)) = self.saved.pop() {
+            self.owner.mem_write(addr, &orig)?;
+        }
+        ptrace::setregs(self.tid, self.regs)?;
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Patch<'a> {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            log::error!("failed to restore tid: {} with err: {:#?}", self.tid, e);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{Frame, HostError, Symbols, UProc};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// Stack samples of a process, counted by stack.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// frame names, the thread name first and then outermost to innermost frame
+    stacks: BTreeMap<Vec<String>, u64>,
+    samples: u64,
+}
+
+impl Profile {
+    /// Stacks sampled, one per thread per sample.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Every distinct stack with the number of times it was sampled, the thread name first
+    /// and then outermost to innermost frame.
+    pub fn stacks(&self) -> impl Iterator<Item = (&[String], u64)> {
+        self.stacks
+            .iter()
+            .map(|(stack, count)| (stack.as_slice(), *count))
+    }
+
+    fn add(&mut self, thread: &str, frames: &[Frame]) {
+        let stack = std::iter::once(thread.to_string())
+            .chain(frames.iter().rev().map(frame_name))
+            .collect();
+        *self.stacks.entry(stack).or_default() += 1;
+        self.samples += 1;
+    }
+
+    /// Write one `thread;outer;...;inner count` line per stack, the folded format read by
+    /// flamegraph tools like `inferno-flamegraph` and `flamegraph.pl`.
+    pub fn write_folded<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(out, "{}", self)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (stack, count) in self.stacks() {
+            writeln!(f, "{} {}", stack.join(";"), count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Function name, or `module+offset` without one. `;` separates frames in the folded format
+/// and spaces the count, neither may appear in a name.
+fn frame_name(frame: &Frame) -> String {
+    let name = match (&frame.function, &frame.module) {
+        (Some(function), _) => function.clone(),
+        (None, Some(module)) => format!("{}+{:#x}", module, frame.offset),
+        (None, None) => format!("{:#x}", frame.ip),
+    };
+    name.replace(';', ":").replace(' ', "_")
+}
+
+impl UProc {
+    /// Sample the stack of every thread `frequency` times per second for `duration`, or until
+    /// the process exits. Between samples all threads run, threads that stop on their own in
+    /// the meantime are let go at the next sample. Threads started during the profile are
+    /// sampled too, code mapped during it is not symbolized.
+    pub fn profile(&self, frequency: u32, duration: Duration) -> Result<Profile, HostError> {
+        let period = Duration::from_secs(1) / frequency.max(1);
+        let symbols = self.symbols()?;
+        let mut profile = Profile::default();
+
+        let start = Instant::now();
+        let mut next = start;
+        self.resume()?;
+        while next - start < duration {
+            next += period;
+            if let Some(delay) = next.checked_duration_since(Instant::now()) {
+                std::thread::sleep(delay);
+            }
+
+            match self.sample(&symbols, &mut profile) {
+                Err(HostError::ProcessExited(stop)) => {
+                    log::info!("pid: {} exited while profiled: {:?}", self.pid, stop);
+                    return Ok(profile);
+                }
+                res => res?,
+            }
+        }
+        self.stop_all()?;
+        Ok(profile)
+    }
+
+    /// Stop every thread, add their stacks to `profile` and let them run again.
+    fn sample(&self, symbols: &Symbols, profile: &mut Profile) -> Result<(), HostError> {
+        while let Some(stop) = self.try_wait_any()? {
+            self.keep_running(stop)?;
+        }
+        self.stop_all()?;
+
+        for tid in self.threads() {
+            let name = std::fs::read_to_string(format!("/proc/{}/task/{}/comm", self.pid, tid))
+                .map(|comm| comm.trim_end().to_string())
+                .unwrap_or_else(|_| tid.to_string());
+            match self.backtrace_with(tid, symbols) {
+                Ok(frames) => profile.add(&name, &frames),
+                // exited since it was stopped, its exit is in the pending stops
+                Err(e) => log::debug!("tid: {} not sampled: {}", tid, e),
+            }
+        }
+
+        self.resume_all()?;
+        loop {
+            let stop = self.pending.borrow_mut().pop_front();
+            match stop {
+                Some(stop) => self.keep_running(stop)?,
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UnwindMethod;
+
+    fn frame(function: Option<&str>, ip: u64) -> Frame {
+        Frame {
+            ip,
+            sp: 0,
+            module: Some("victim".to_string()),
+            function: function.map(str::to_string),
+            offset: 0x10,
+            method: UnwindMethod::Cfi,
+        }
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let mut profile = Profile::default();
+        let inner = [frame(Some("sleep"), 2), frame(Some("main"), 1)];
+        let unknown = [frame(None, 3), frame(Some("<a as b>::c;d"), 1)];
+        profile.add("victim", &inner);
+        profile.add("victim", &unknown);
+        profile.add("victim", &inner);
+
+        assert_eq!(profile.samples(), 3);
+        assert_eq!(
+            profile.to_string(),
+            "victim;<a_as_b>::c:d;victim+0x10 1\nvictim;main;sleep 2\n"
+        );
+    }
+}
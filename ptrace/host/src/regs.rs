@@ -0,0 +1,539 @@
+use crate::{Arch, HostError, UProc};
+use nix::{
+    errno::Errno,
+    libc::{self, user_fpregs_struct, user_regs_struct},
+    unistd::Pid,
+};
+use std::{fmt, mem::MaybeUninit, ptr};
+
+/// Bits of `eflags`, with the names used by debuggers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Eflags(pub u64);
+
+impl Eflags {
+    pub const CF: u64 = 1 << 0;
+    pub const PF: u64 = 1 << 2;
+    pub const AF: u64 = 1 << 4;
+    pub const ZF: u64 = 1 << 6;
+    pub const SF: u64 = 1 << 7;
+    pub const TF: u64 = 1 << 8;
+    pub const IF: u64 = 1 << 9;
+    pub const DF: u64 = 1 << 10;
+    pub const OF: u64 = 1 << 11;
+    pub const RF: u64 = 1 << 16;
+    pub const VM: u64 = 1 << 17;
+    pub const AC: u64 = 1 << 18;
+    pub const ID: u64 = 1 << 21;
+
+    const NAMES: [(u64, &'static str); 13] = [
+        (Self::CF, "CF"),
+        (Self::PF, "PF"),
+        (Self::AF, "AF"),
+        (Self::ZF, "ZF"),
+        (Self::SF, "SF"),
+        (Self::TF, "TF"),
+        (Self::IF, "IF"),
+        (Self::DF, "DF"),
+        (Self::OF, "OF"),
+        (Self::RF, "RF"),
+        (Self::VM, "VM"),
+        (Self::AC, "AC"),
+        (Self::ID, "ID"),
+    ];
+
+    pub fn contains(self, flag: u64) -> bool {
+        self.0 & flag == flag
+    }
+
+    /// Names of the set flags, lowest bit first.
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl fmt::Display for Eflags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[ ")?;
+        for name in self.names() {
+            write!(f, "{} ", name)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Slot of a 64-bit register in `user_regs_struct`, `pc` and `sp` are aliases of `rip` and `rsp`.
+fn slot<'a>(regs: &'a mut user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    Some(match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" => &mut regs.rbp,
+        "rsp" | "sp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" | "pc" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "orig_rax" => &mut regs.orig_rax,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        _ => return None,
+    })
+}
+
+/// 64-bit register holding a 32-bit one.
+fn widened(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "eax" => "rax",
+        "ebx" => "rbx",
+        "ecx" => "rcx",
+        "edx" => "rdx",
+        "esi" => "rsi",
+        "edi" => "rdi",
+        "ebp" => "rbp",
+        "esp" => "rsp",
+        "eip" => "rip",
+        _ => return None,
+    })
+}
+
+/// Registers holding addresses, printed in hex only.
+fn is_pointer(name: &str) -> bool {
+    matches!(
+        name,
+        "rip" | "rsp" | "rbp" | "eip" | "esp" | "ebp" | "fs_base" | "gs_base"
+    )
+}
+
+/// One register that differs between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub name: &'static str,
+    pub old: u64,
+    pub new: u64,
+}
+
+/// Snapshot of the general purpose registers of a thread, named after the tracee's
+/// architecture.
+#[derive(Clone, Copy)]
+pub struct Registers {
+    raw: user_regs_struct,
+    arch: &'static dyn Arch,
+}
+
+impl Registers {
+    pub fn new(raw: user_regs_struct, arch: &'static dyn Arch) -> Self {
+        Self { raw, arch }
+    }
+
+    pub fn raw(&self) -> &user_regs_struct {
+        &self.raw
+    }
+
+    pub fn raw_mut(&mut self) -> &mut user_regs_struct {
+        &mut self.raw
+    }
+
+    pub fn arch(&self) -> &'static dyn Arch {
+        self.arch
+    }
+
+    pub fn ip(&self) -> u64 {
+        self.arch.ip(&self.raw)
+    }
+
+    pub fn sp(&self) -> u64 {
+        self.arch.sp(&self.raw)
+    }
+
+    pub fn eflags(&self) -> Eflags {
+        Eflags(self.raw.eflags)
+    }
+
+    /// Number of the syscall the thread was interrupted in, which the kernel restarts when the
+    /// thread resumes: `orig_rax` holds the syscall and `rax` one of the ERESTART* codes.
+    pub fn interrupted_syscall(&self) -> Option<u64> {
+        let nr = self.raw.orig_rax as i64;
+        (nr >= 0 && self.arch.syscall_result(&self.raw).is_restart()).then_some(nr as u64)
+    }
+
+    /// Register by name, 64-bit names like `rax` or 32-bit ones like `eax`, which read the low
+    /// half.
+    pub fn get(&self, name: &str) -> Option<u64> {
+        let mut raw = self.raw;
+        match widened(name) {
+            Some(wide) => slot(&mut raw, wide).map(|v| *v as u32 as u64),
+            None => slot(&mut raw, name).map(|v| *v),
+        }
+    }
+
+    /// Set a register by name. Like the instructions writing them, 32-bit names zero the high
+    /// half.
+    pub fn set(&mut self, name: &str, value: u64) -> Result<(), HostError> {
+        let (wide, value) = match widened(name) {
+            Some(wide) => (wide, value as u32 as u64),
+            None => (name, value),
+        };
+        let slot =
+            slot(&mut self.raw, wide).ok_or_else(|| HostError::NoSuchRegister(name.into()))?;
+        *slot = value;
+        Ok(())
+    }
+
+    /// Registers of the architecture with their names, in `info registers` order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, u64)> {
+        self.arch.registers(&self.raw).into_iter()
+    }
+
+    /// Registers whose value differs in `other`, a later snapshot.
+    pub fn diff(&self, other: &Registers) -> Vec<RegisterChange> {
+        self.iter()
+            .zip(other.iter())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((name, old), (_, new))| RegisterChange { name, old, new })
+            .collect()
+    }
+}
+
+impl fmt::Debug for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl PartialEq for Registers {
+    fn eq(&self, other: &Self) -> bool {
+        self.arch.name() == other.arch.name() && self.iter().eq(other.iter())
+    }
+}
+
+/// One line per register like gdb's `info registers`: name, hex value and natural value.
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.iter() {
+            let hex = format!("{:#x}", value);
+            write!(f, "{:<15}{:<19}", name, hex)?;
+            if name == "eflags" {
+                writeln!(f, "{}", Eflags(value))?;
+            } else if is_pointer(name) {
+                writeln!(f, "{}", hex)?;
+            } else if self.arch.word_size() == 4 {
+                writeln!(f, "{}", value as u32 as i32)?;
+            } else {
+                writeln!(f, "{}", value as i64)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Convert an x87 80-bit extended precision value to the nearest `f64`.
+fn f80_to_f64(bytes: [u8; 10]) -> f64 {
+    let mantissa = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let se = u16::from_le_bytes([bytes[8], bytes[9]]);
+    let sign = if se & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = (se & 0x7FFF) as i32;
+
+    let value = match exp {
+        0 if mantissa == 0 => 0.0,
+        0x7FFF if mantissa << 1 == 0 => f64::INFINITY,
+        0x7FFF => f64::NAN,
+        // explicit integer bit, denormals use the exponent of 1
+        _ => mantissa as f64 * 2f64.powi(exp.max(1) - 16383 - 63),
+    };
+    sign * value
+}
+
+/// x87 stack registers, `st0` to `st7`.
+const ST_REGISTERS: usize = 8;
+
+/// x87, MMX and SSE state of a thread, in the `fxsave` layout returned by PTRACE_GETFPREGS.
+#[derive(Clone, Copy)]
+pub struct FpRegisters {
+    raw: user_fpregs_struct,
+    arch: &'static dyn Arch,
+}
+
+impl FpRegisters {
+    pub fn raw(&self) -> &user_fpregs_struct {
+        &self.raw
+    }
+
+    /// x87 control word.
+    pub fn fcw(&self) -> u16 {
+        self.raw.cwd
+    }
+
+    /// x87 status word.
+    pub fn fsw(&self) -> u16 {
+        self.raw.swd
+    }
+
+    pub fn mxcsr(&self) -> u32 {
+        self.raw.mxcsr
+    }
+
+    /// SSE registers of the tracee's architecture, 16 on x86_64 and 8 on i386.
+    pub fn xmm_count(&self) -> usize {
+        if self.arch.word_size() == 4 {
+            8
+        } else {
+            16
+        }
+    }
+
+    /// Raw 80-bit value of `st(i)`, counted from the top of the stack, `None` past `st7`.
+    pub fn st(&self, i: usize) -> Option<[u8; 10]> {
+        if i >= ST_REGISTERS {
+            return None;
+        }
+        let words = &self.raw.st_space[i * 4..i * 4 + 4];
+        let mut bytes = [0u8; 10];
+        for (chunk, word) in bytes.chunks_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+        }
+        Some(bytes)
+    }
+
+    pub fn st_f64(&self, i: usize) -> Option<f64> {
+        self.st(i).map(f80_to_f64)
+    }
+
+    /// `xmm(i)`, `None` past the last one of the architecture.
+    pub fn xmm(&self, i: usize) -> Option<u128> {
+        if i >= self.xmm_count() {
+            return None;
+        }
+        let words = &self.raw.xmm_space[i * 4..i * 4 + 4];
+        Some(
+            words
+                .iter()
+                .rev()
+                .fold(0u128, |acc, word| acc << 32 | *word as u128),
+        )
+    }
+
+    pub fn set_xmm(&mut self, i: usize, value: u128) -> Result<(), HostError> {
+        if i >= self.xmm_count() {
+            return Err(HostError::NoSuchRegister(format!("xmm{}", i)));
+        }
+        for (j, word) in self.raw.xmm_space[i * 4..i * 4 + 4].iter_mut().enumerate() {
+            *word = (value >> (32 * j)) as u32;
+        }
+        Ok(())
+    }
+
+    pub fn xmm_f32(&self, i: usize) -> Option<[f32; 4]> {
+        let v = self.xmm(i)?;
+        Some([0, 1, 2, 3].map(|j| f32::from_bits((v >> (32 * j)) as u32)))
+    }
+
+    pub fn xmm_f64(&self, i: usize) -> Option<[f64; 2]> {
+        let v = self.xmm(i)?;
+        Some([0, 1].map(|j| f64::from_bits((v >> (64 * j)) as u64)))
+    }
+}
+
+impl fmt::Debug for FpRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FpRegisters")
+            .field("fcw", &self.fcw())
+            .field("fsw", &self.fsw())
+            .field("mxcsr", &self.mxcsr())
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for FpRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for i in 0..ST_REGISTERS {
+            writeln!(f, "st{:<13}{}", i, self.st_f64(i).unwrap())?;
+        }
+        writeln!(f, "{:<15}{:#x}", "fctrl", self.fcw())?;
+        writeln!(f, "{:<15}{:#x}", "fstat", self.fsw())?;
+        writeln!(f, "{:<15}{:#x}", "mxcsr", self.mxcsr())?;
+        for i in 0..self.xmm_count() {
+            writeln!(f, "xmm{:<12}{:#034x}", i, self.xmm(i).unwrap())?;
+        }
+        Ok(())
+    }
+}
+
+impl UProc {
+    /// General purpose registers of the current thread.
+    pub fn registers(&self) -> Result<Registers, HostError> {
+        self.registers_of(self.current_thread())
+    }
+
+    pub fn registers_of(&self, tid: Pid) -> Result<Registers, HostError> {
+        Ok(Registers::new(nix::sys::ptrace::getregs(tid)?, self.arch()))
+    }
+
+    pub fn set_registers(&self, regs: &Registers) -> Result<(), HostError> {
+        nix::sys::ptrace::setregs(self.current_thread(), regs.raw)?;
+        Ok(())
+    }
+
+    /// Floating point and SSE registers of the current thread.
+    pub fn fp_registers(&self) -> Result<FpRegisters, HostError> {
+        self.fp_registers_of(self.current_thread())
+    }
+
+    pub fn fp_registers_of(&self, tid: Pid) -> Result<FpRegisters, HostError> {
+        let mut raw = MaybeUninit::<user_fpregs_struct>::uninit();
+        // SAFETY: PTRACE_GETFPREGS fills a whole user_fpregs_struct
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                tid.as_raw(),
+                ptr::null_mut::<libc::c_void>(),
+                raw.as_mut_ptr(),
+            )
+        };
+        Errno::result(res)?;
+        // SAFETY: initialized by the kernel on success
+        Ok(FpRegisters {
+            raw: unsafe { raw.assume_init() },
+            arch: self.arch(),
+        })
+    }
+
+    pub fn set_fp_registers(&self, regs: &FpRegisters) -> Result<(), HostError> {
+        // SAFETY: PTRACE_SETFPREGS only reads the struct
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_SETFPREGS,
+                self.current_thread().as_raw(),
+                ptr::null_mut::<libc::c_void>(),
+                &regs.raw as *const user_fpregs_struct,
+            )
+        };
+        Errno::result(res)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{I386, X86_64};
+
+    fn zeroed() -> user_regs_struct {
+        // SAFETY: user_regs_struct is plain integers
+        unsafe { std::mem::zeroed() }
+    }
+
+    #[test]
+    fn eflags_names() {
+        let flags = Eflags(0x246);
+        assert!(flags.contains(Eflags::ZF));
+        assert!(!flags.contains(Eflags::CF));
+        assert_eq!(flags.names(), ["PF", "ZF", "IF"]);
+        assert_eq!(flags.to_string(), "[ PF ZF IF ]");
+    }
+
+    #[test]
+    fn get_set_by_name() {
+        let mut regs = Registers::new(zeroed(), &X86_64);
+        regs.set("rax", 0x1_0000_0002).unwrap();
+        regs.set("pc", 0x401000).unwrap();
+        assert_eq!(regs.get("rax"), Some(0x1_0000_0002));
+        assert_eq!(regs.get("eax"), Some(2));
+        assert_eq!(regs.get("rip"), Some(0x401000));
+
+        regs.set("eax", u64::MAX).unwrap();
+        assert_eq!(regs.get("rax"), Some(0xFFFF_FFFF));
+        assert_eq!(regs.get("xmm0"), None);
+        assert!(matches!(
+            regs.set("bogus", 1),
+            Err(HostError::NoSuchRegister(_))
+        ));
+    }
+
+    #[test]
+    fn diff_snapshots() {
+        let before = Registers::new(zeroed(), &X86_64);
+        let mut after = before;
+        after.set("rax", 39).unwrap();
+        after.set("rip", 2).unwrap();
+
+        assert_eq!(
+            before.diff(&after),
+            [
+                RegisterChange {
+                    name: "rax",
+                    old: 0,
+                    new: 39
+                },
+                RegisterChange {
+                    name: "rip",
+                    old: 0,
+                    new: 2
+                },
+            ]
+        );
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn info_registers() {
+        let mut raw = zeroed();
+        raw.rax = -1i64 as u64;
+        raw.rip = 0x401000;
+        raw.eflags = 0x246;
+        let dump = Registers::new(raw, &X86_64).to_string();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0], "rax            0xffffffffffffffff -1");
+        assert!(lines.contains(&"rip            0x401000           0x401000"));
+        assert!(lines.contains(&"eflags         0x246              [ PF ZF IF ]"));
+
+        let dump = Registers::new(raw, &I386).to_string();
+        assert!(dump.starts_with("eax            0xffffffff         -1\n"));
+    }
+
+    #[test]
+    fn f80() {
+        // 1.0: integer bit set, biased exponent 16383
+        let one = [0, 0, 0, 0, 0, 0, 0, 0x80, 0xFF, 0x3F];
+        assert_eq!(f80_to_f64(one), 1.0);
+        let minus_three = [0, 0, 0, 0, 0, 0, 0, 0xC0, 0x00, 0xC0];
+        assert_eq!(f80_to_f64(minus_three), -3.0);
+        assert_eq!(f80_to_f64([0; 10]), 0.0);
+        let inf = [0, 0, 0, 0, 0, 0, 0, 0x80, 0xFF, 0x7F];
+        assert_eq!(f80_to_f64(inf), f64::INFINITY);
+    }
+
+    #[test]
+    fn i386_xmm() {
+        let mut fp = FpRegisters {
+            // SAFETY: user_fpregs_struct is plain integers
+            raw: unsafe { std::mem::zeroed() },
+            arch: &I386,
+        };
+        assert_eq!(fp.xmm_count(), 8);
+        assert_eq!(fp.xmm(7), Some(0));
+        assert_eq!(fp.xmm(8), None);
+        assert!(fp.set_xmm(8, 1).is_err());
+        let dump = fp.to_string();
+        assert!(dump.contains("xmm7 "));
+        assert!(!dump.contains("xmm8 "));
+    }
+}
@@ -0,0 +1,551 @@
+use crate::parse_int;
+use host::{Halt, HostError, Symbols, UProc, WatchKind};
+use nix::{
+    sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    unistd::Pid,
+};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use std::{error::Error, path::PathBuf};
+
+type CmdResult = Result<Flow, Box<dyn Error>>;
+
+const HELP: &str = "\
+break LOC            set a breakpoint at LOC, an address, symbol or $reg, optionally +OFFSET
+watch LOC [LEN]      stop after LEN bytes at LOC are written, LEN 1, 2, 4 or 8 (default)
+awatch LOC [LEN]     stop after LEN bytes at LOC are read or written
+delete [N]           delete breakpoint or watchpoint N, or all of them
+info breakpoints     list the breakpoints and watchpoints
+info threads         list the threads, the current one marked with *
+thread TID           select the thread used by the other commands
+continue             resume all threads until a breakpoint, a signal, an exec or Ctrl-C
+step [N]             execute N instructions, running calls to completion
+stepi [N]            execute N instructions, following calls
+x/NFU LOC            examine N units of size U (b h w g) in format F (x d u s) at LOC
+regs [NAME...]       show all registers, or the named ones
+set reg NAME VALUE   change a register, also `set $NAME = VALUE`
+backtrace            show the stack of the current thread
+quit                 detach, or kill a process started by the debugger
+An empty line repeats the last step, stepi or continue.";
+
+/// Commands run again by an empty line.
+const REPEATED: [&str; 8] = ["step", "s", "next", "n", "stepi", "si", "continue", "c"];
+
+#[derive(Debug, PartialEq, Eq)]
+enum Flow {
+    Next,
+    Quit,
+}
+
+/// Most bytes one `x` reads, like gdb's limit on value sizes.
+pub(crate) const MAX_EXAMINE: usize = 64 * 1024;
+
+/// How `x` shows memory, remembered from one use to the next like in gdb.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Examine {
+    count: usize,
+    format: char,
+    size: usize,
+}
+
+impl Default for Examine {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            format: 'x',
+            size: 4,
+        }
+    }
+}
+
+/// Parse the `NFU` after `x/`, any part may be left out and defaults to the last one used,
+/// except the count which defaults to 1.
+fn parse_examine(spec: &str, last: Examine) -> Result<Examine, String> {
+    let digits = spec.chars().take_while(|c| c.is_ascii_digit()).count();
+    let mut examine = Examine { count: 1, ..last };
+    if digits > 0 {
+        examine.count = spec[..digits].parse().map_err(|e| format!("{}", e))?;
+    }
+    for c in spec[digits..].chars() {
+        match c {
+            'x' | 'd' | 'u' | 's' => examine.format = c,
+            'b' => examine.size = 1,
+            'h' => examine.size = 2,
+            'w' => examine.size = 4,
+            'g' => examine.size = 8,
+            _ => return Err(format!("bad format letter `{}` in `x/{}`", c, spec)),
+        }
+    }
+    match examine.count.checked_mul(examine.size) {
+        Some(total) if total <= MAX_EXAMINE => Ok(examine),
+        _ => Err(format!(
+            "`x/{}` reads more than {} bytes",
+            spec, MAX_EXAMINE
+        )),
+    }
+}
+
+/// Format one unit of memory, little endian.
+fn format_unit(bytes: &[u8], format: char) -> String {
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    let value = u64::from_le_bytes(buf);
+    let bits = bytes.len() * 8;
+    match format {
+        'd' => {
+            let shift = 64 - bits;
+            (((value << shift) as i64) >> shift).to_string()
+        }
+        'u' => value.to_string(),
+        _ => format!("{:#0width$x}", value, width = bytes.len() * 2 + 2),
+    }
+}
+
+/// Interactive debugger on one process, with gdb-like commands.
+struct Repl {
+    proc: UProc,
+    /// user breakpoints by number, numbers are never reused
+    breakpoints: Vec<(usize, u64)>,
+    /// watchpoints by number, shared with breakpoints, and their debug register
+    watchpoints: Vec<(usize, usize)>,
+    next_breakpoint: usize,
+    examine: Examine,
+    /// symbols of the modules mapped when last loaded, reloaded after the process ran
+    symbols: Option<Symbols>,
+}
+
+impl Repl {
+    fn new(proc: UProc) -> Self {
+        Self {
+            proc,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_breakpoint: 1,
+            examine: Examine::default(),
+            symbols: None,
+        }
+    }
+
+    fn symbols(&mut self) -> Result<&Symbols, HostError> {
+        if self.symbols.is_none() {
+            self.symbols = Some(self.proc.symbols()?);
+        }
+        Ok(self.symbols.as_ref().unwrap())
+    }
+
+    /// `addr` followed by its symbol, if any.
+    fn location(&mut self, addr: u64) -> String {
+        match self.symbols().ok().and_then(|s| s.symbolize(addr)) {
+            Some(location) => format!("{:#x} in {}", addr, location),
+            None => format!("{:#x}", addr),
+        }
+    }
+
+    /// Number, `$register` or symbol, with an optional `+OFFSET`.
+    fn value(&mut self, s: &str) -> Result<u64, Box<dyn Error>> {
+        if let Some((base, offset)) = s.split_once('+') {
+            return Ok(self.value(base)?.wrapping_add(parse_int(offset)?));
+        }
+        if let Some(name) = s.strip_prefix('$') {
+            return Ok(self
+                .proc
+                .registers()?
+                .get(name)
+                .ok_or_else(|| HostError::NoSuchRegister(name.to_string()))?);
+        }
+        if let Ok(value) = parse_int(s) {
+            return Ok(value);
+        }
+        Ok(self
+            .symbols()?
+            .resolve(s)
+            .ok_or_else(|| HostError::SymbolNotFound(s.to_string()))?)
+    }
+
+    fn execute(&mut self, line: &str) -> CmdResult {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&cmd, args)) = words.split_first() else {
+            return Ok(Flow::Next);
+        };
+        let count = || -> Result<usize, Box<dyn Error>> {
+            Ok(args.first().map_or(Ok(1), |n| parse_int(n))? as usize)
+        };
+        match cmd {
+            "help" | "h" => println!("{}", HELP),
+            "quit" | "q" => return Ok(Flow::Quit),
+            "break" | "b" => {
+                let loc = args.first().ok_or("break needs a location")?;
+                let addr = self.value(loc)?;
+                if let Some((id, _)) = self.breakpoints.iter().find(|(_, a)| *a == addr) {
+                    return Err(format!("breakpoint {} is already at {:#x}", id, addr).into());
+                }
+                self.proc.set_breakpoint(addr)?;
+                let id = self.next_breakpoint;
+                self.next_breakpoint += 1;
+                self.breakpoints.push((id, addr));
+                println!("Breakpoint {} at {}", id, self.location(addr));
+            }
+            "watch" | "awatch" => {
+                let kind = match cmd {
+                    "watch" => WatchKind::Write,
+                    _ => WatchKind::ReadWrite,
+                };
+                let loc = args.first().ok_or("watch needs a location")?;
+                let addr = self.value(loc)?;
+                let len = args.get(1).map_or(Ok(8), |len| parse_int(len))? as usize;
+                let slot = self.proc.set_watchpoint(addr, len, kind)?;
+                let id = self.next_breakpoint;
+                self.next_breakpoint += 1;
+                self.watchpoints.push((id, slot));
+                println!("Watchpoint {}: {}", id, self.location(addr));
+            }
+            "delete" | "d" => match args.first() {
+                Some(id) => {
+                    let id = parse_int(id)? as usize;
+                    if let Some(i) = self.watchpoints.iter().position(|(n, _)| *n == id) {
+                        self.proc.remove_watchpoint(self.watchpoints[i].1)?;
+                        self.watchpoints.remove(i);
+                        return Ok(Flow::Next);
+                    }
+                    let i = self
+                        .breakpoints
+                        .iter()
+                        .position(|(n, _)| *n == id)
+                        .ok_or_else(|| format!("no breakpoint number {}", id))?;
+                    self.proc.remove_breakpoint(self.breakpoints[i].1)?;
+                    self.breakpoints.remove(i);
+                }
+                None => {
+                    // forget each one only once removed, the rest stay listed on failure
+                    while let Some(&(_, addr)) = self.breakpoints.last() {
+                        self.proc.remove_breakpoint(addr)?;
+                        self.breakpoints.pop();
+                    }
+                    while let Some(&(_, slot)) = self.watchpoints.last() {
+                        self.proc.remove_watchpoint(slot)?;
+                        self.watchpoints.pop();
+                    }
+                }
+            },
+            "info" | "i" => match args.first().copied() {
+                Some("breakpoints" | "break" | "b") => {
+                    if self.breakpoints.is_empty() && self.watchpoints.is_empty() {
+                        println!("No breakpoints");
+                    }
+                    for (id, addr) in self.breakpoints.clone() {
+                        println!("{:<4}{}", id, self.location(addr));
+                    }
+                    for watchpoint in self.proc.watchpoints() {
+                        let id = self.watchpoint_id(watchpoint.slot);
+                        println!(
+                            "{:<4}{:?} {} bytes at {}",
+                            id,
+                            watchpoint.kind,
+                            watchpoint.len,
+                            self.location(watchpoint.addr)
+                        );
+                    }
+                }
+                Some("threads" | "thread") => {
+                    let current = self.proc.current_thread();
+                    for tid in self.proc.threads() {
+                        let ip = self.proc.registers_of(tid)?.ip();
+                        let mark = if tid == current { '*' } else { ' ' };
+                        println!("{} {:<8}{}", mark, tid, self.location(ip));
+                    }
+                }
+                Some("registers" | "reg" | "r") => return self.execute("regs"),
+                _ => return Err("info breakpoints, threads or registers".into()),
+            },
+            "thread" | "t" => {
+                let tid = args.first().ok_or("thread needs a tid")?;
+                self.proc
+                    .select_thread(Pid::from_raw(parse_int(tid)? as i32))?;
+                let ip = self.proc.registers()?.ip();
+                println!(
+                    "[thread {}] {}",
+                    self.proc.current_thread(),
+                    self.location(ip)
+                );
+            }
+            "continue" | "c" => {
+                let halt = with_interrupts(|| self.proc.cont());
+                self.symbols = None;
+                return self.halted(halt);
+            }
+            "step" | "s" | "next" | "n" => {
+                let mut halt = Ok(Halt::Stepped { addr: 0 });
+                for _ in 0..count()? {
+                    halt = with_interrupts(|| self.proc.step_over());
+                    if !matches!(halt, Ok(Halt::Stepped { .. })) {
+                        break;
+                    }
+                }
+                self.symbols = None;
+                return self.halted(halt);
+            }
+            "stepi" | "si" => {
+                let mut addr = Ok(0);
+                for _ in 0..count()? {
+                    addr = self.proc.step_instruction();
+                    if addr.is_err() {
+                        break;
+                    }
+                }
+                return self.halted(addr.map(|addr| Halt::Stepped { addr }));
+            }
+            "regs" | "registers" => {
+                let regs = self.proc.registers()?;
+                if args.is_empty() {
+                    print!("{}", regs);
+                }
+                for name in args {
+                    let name = name.trim_start_matches('$');
+                    let value = regs
+                        .get(name)
+                        .ok_or_else(|| HostError::NoSuchRegister(name.to_string()))?;
+                    println!("{:<15}{:#x}", name, value);
+                }
+            }
+            "set" => {
+                let (name, value) = match args {
+                    ["reg", name, value] | [name, "=", value] | [name, value] => {
+                        (name.trim_start_matches('$'), value)
+                    }
+                    _ => return Err("set reg NAME VALUE".into()),
+                };
+                let value = self.value(value)?;
+                let mut regs = self.proc.registers()?;
+                regs.set(name, value)?;
+                self.proc.set_registers(&regs)?;
+            }
+            "backtrace" | "bt" | "where" => {
+                let tid = self.proc.current_thread();
+                self.symbols()?;
+                let symbols = self.symbols.as_ref().unwrap();
+                let frames = self.proc.backtrace_with(tid, symbols)?;
+                for (i, frame) in frames.iter().enumerate() {
+                    println!("#{:<3}{}", i, frame);
+                }
+            }
+            cmd if cmd == "x" || cmd.starts_with("x/") => {
+                let spec = cmd.strip_prefix("x/").unwrap_or_default();
+                let examine = parse_examine(spec, self.examine)?;
+                let loc = args.first().ok_or("x needs a location")?;
+                let addr = self.value(loc)?;
+                self.examine = examine;
+                self.examine_memory(addr, examine)?;
+            }
+            _ => return Err(format!("unknown command `{}`, try help", cmd).into()),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn examine_memory(&mut self, mut addr: u64, examine: Examine) -> Result<(), HostError> {
+        if examine.format == 's' {
+            for _ in 0..examine.count {
+                let s = self.proc.mem_read_cstr(addr, 4096)?;
+                println!(
+                    "{}:\t{:?}",
+                    self.location(addr),
+                    String::from_utf8_lossy(&s)
+                );
+                // stop at the top of the address space
+                match addr.checked_add(s.len() as u64 + 1) {
+                    Some(next) => addr = next,
+                    None => break,
+                }
+            }
+            return Ok(());
+        }
+
+        let data = self.proc.mem_read(addr, examine.count * examine.size)?;
+        let per_line = (16 / examine.size).min(8);
+        for line in data.chunks(per_line * examine.size) {
+            let units: Vec<String> = line
+                .chunks(examine.size)
+                .map(|unit| format_unit(unit, examine.format))
+                .collect();
+            println!("{}:\t{}", self.location(addr), units.join("\t"));
+            addr = addr.wrapping_add(line.len() as u64);
+        }
+        Ok(())
+    }
+
+    fn watchpoint_id(&self, slot: usize) -> usize {
+        self.watchpoints
+            .iter()
+            .find(|(_, s)| *s == slot)
+            .map_or(0, |(id, _)| *id)
+    }
+
+    /// Report where the process stopped, and quit when it is gone.
+    fn halted(&mut self, halt: Result<Halt, HostError>) -> CmdResult {
+        let tid = self.proc.current_thread();
+        match halt {
+            Ok(Halt::Breakpoint { tid, addr }) => {
+                let id = self
+                    .breakpoints
+                    .iter()
+                    .find(|(_, a)| *a == addr)
+                    .map_or(0, |(id, _)| *id);
+                println!(
+                    "Breakpoint {}, [thread {}] {}",
+                    id,
+                    tid,
+                    self.location(addr)
+                );
+            }
+            Ok(Halt::Watchpoint { tid, watchpoint }) => {
+                let ip = self.proc.registers()?.ip();
+                let value = self.proc.mem_read(watchpoint.addr, watchpoint.len)?;
+                println!(
+                    "Watchpoint {}, [thread {}] {}\n{} = {}",
+                    self.watchpoint_id(watchpoint.slot),
+                    tid,
+                    self.location(ip),
+                    self.location(watchpoint.addr),
+                    format_unit(&value, 'x')
+                );
+            }
+            Ok(Halt::Signal { tid, signal }) => {
+                let ip = self.proc.registers()?.ip();
+                println!(
+                    "[thread {}] received {}, {}",
+                    tid,
+                    signal,
+                    self.location(ip)
+                );
+            }
+            Ok(Halt::Fork { tid, child }) => {
+                // dropping it detaches it, the debugger follows the parent
+                drop(self.proc.take_child(child));
+                println!("[thread {}] forked process {}, detached", tid, child);
+            }
+            Ok(Halt::Exec) => {
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+                self.symbols = None;
+                let exe = self.proc.exe()?;
+                let ip = self.proc.registers()?.ip();
+                println!(
+                    "[process {}] executing {}, {}",
+                    self.proc.pid(),
+                    exe.display(),
+                    self.location(ip)
+                );
+            }
+            Ok(Halt::Stepped { addr }) => println!("{}", self.location(addr)),
+            Ok(Halt::Interrupted) => {
+                let ip = self.proc.registers()?.ip();
+                println!("Interrupted, [thread {}] {}", tid, self.location(ip));
+            }
+            Err(HostError::ProcessExited(stop)) => {
+                println!("[process {} exited: {:?}]", self.proc.pid(), stop.reason);
+                return Ok(Flow::Quit);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Flow::Next)
+    }
+}
+
+extern "C" fn on_interrupt(_: nix::libc::c_int) {}
+
+/// Run `f` with a SIGINT handler that only interrupts the wait for the tracee, so that Ctrl-C
+/// stops the process instead of the debugger.
+fn with_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let action = SigAction::new(
+        SigHandler::Handler(on_interrupt),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    // SAFETY: the handler does nothing
+    let old = unsafe { signal::sigaction(Signal::SIGINT, &action) };
+    let res = f();
+    if let Ok(old) = old {
+        // SAFETY: puts back the previous action
+        unsafe { signal::sigaction(Signal::SIGINT, &old) }.ok();
+    }
+    res
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".host_history"))
+}
+
+/// Read commands until `quit` or end of input, then drop the process: an attached one is
+/// detached and a spawned one killed.
+pub fn run(proc: UProc) -> Result<(), ReadlineError> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        // no history yet on the first run
+        editor.load_history(path).ok();
+    }
+
+    let mut repl = Repl::new(proc);
+    let ip = repl.proc.registers().map(|r| r.ip()).unwrap_or_default();
+    println!("[process {}] {}", repl.proc.pid(), repl.location(ip));
+
+    let mut last = String::new();
+    loop {
+        let line = match editor.readline("(host) ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+        let line = if line.trim().is_empty() {
+            let cmd = last.split_whitespace().next().unwrap_or_default();
+            if !REPEATED.contains(&cmd) {
+                continue;
+            }
+            last.clone()
+        } else {
+            editor.add_history_entry(line.as_str())?;
+            line
+        };
+        match repl.execute(&line) {
+            Ok(Flow::Next) => {}
+            Ok(Flow::Quit) => break,
+            Err(e) => println!("error: {}", e),
+        }
+        last = line;
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            log::warn!("cannot save history to {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examine_spec() {
+        let last = Examine::default();
+        assert_eq!(
+            parse_examine("16xb", last),
+            Ok(Examine {
+                count: 16,
+                format: 'x',
+                size: 1
+            })
+        );
+        let giant = parse_examine("2dg", last).unwrap();
+        assert_eq!(parse_examine("", giant).unwrap().size, 8);
+        assert_eq!(parse_examine("", giant).unwrap().count, 1);
+        assert!(parse_examine("4q", last).is_err());
+        assert_eq!(parse_examine("8192g", last).unwrap().count, 8192);
+        assert!(parse_examine("8193g", last).is_err());
+        assert!(parse_examine("4611686018427387904g", last).is_err());
+
+        assert_eq!(format_unit(&[0x0d, 0xf0], 'x'), "0xf00d");
+        assert_eq!(format_unit(&[0xff], 'd'), "-1");
+        assert_eq!(format_unit(&[0xff], 'u'), "255");
+    }
+}
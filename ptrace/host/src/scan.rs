@@ -0,0 +1,348 @@
+use crate::{
+    mem::{bytes_of, from_bytes},
+    HostError, MapRegion, Pod, UProc,
+};
+use std::{fmt, mem::size_of, str::FromStr};
+
+/// Default size of a single read from the tracee.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Byte pattern where each byte is either exact or a wildcard, written as `48 8b ?? 05`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    bytes: Vec<Option<u8>>,
+}
+
+impl Pattern {
+    pub fn exact(bytes: &[u8]) -> Self {
+        Self {
+            bytes: bytes.iter().copied().map(Some).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() >= self.len()
+            && self
+                .bytes
+                .iter()
+                .zip(data)
+                .all(|(p, b)| p.is_none_or(|p| p == *b))
+    }
+
+    /// Offsets of every match in `data`, overlapping ones included.
+    pub fn find_all(&self, data: &[u8]) -> Vec<usize> {
+        if self.is_empty() || data.len() < self.len() {
+            return Vec::new();
+        }
+        (0..=data.len() - self.len())
+            .filter(|&i| self.matches(&data[i..]))
+            .collect()
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = HostError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || HostError::PatternParse(s.to_string());
+        let bytes = s
+            .split_whitespace()
+            .map(|tok| match tok {
+                "?" | "??" => Ok(None),
+                // from_str_radix also takes a sign, like `+f`
+                _ if tok.len() == 2 && tok.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                    u8::from_str_radix(tok, 16).map(Some).map_err(|_| err())
+                }
+                _ => Err(err()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if bytes.is_empty() {
+            return Err(err());
+        }
+        Ok(Self { bytes })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.bytes.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            match b {
+                Some(b) => write!(f, "{:02x}", b)?,
+                None => write!(f, "??")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a value must have changed since the previous scan to stay a hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanFilter<T> {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    Equal(T),
+}
+
+impl<T: PartialOrd> ScanFilter<T> {
+    fn keep(&self, old: &T, new: &T) -> bool {
+        match self {
+            ScanFilter::Changed => new != old,
+            ScanFilter::Unchanged => new == old,
+            ScanFilter::Increased => new > old,
+            ScanFilter::Decreased => new < old,
+            ScanFilter::Equal(value) => new == value,
+        }
+    }
+}
+
+/// Address of a value found by a scan, with the value seen by the last scan.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit<T> {
+    pub addr: u64,
+    pub value: T,
+}
+
+/// Search of the readable memory of a tracee. Regions are read `chunk_size` bytes at a time,
+/// regions that fail to read, like `[vvar]`, are skipped. Matches spanning two regions are not
+/// found.
+#[derive(Clone)]
+pub struct Scanner<'a> {
+    owner: &'a UProc,
+    chunk_size: usize,
+    align: u64,
+    writable: bool,
+    range: Option<(u64, u64)>,
+    path: Option<String>,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Only report matches at addresses that are a multiple of `align`.
+    pub fn align(mut self, align: u64) -> Self {
+        self.align = align.max(1);
+        self
+    }
+
+    /// Only scan writable regions, where program data lives.
+    pub fn writable(mut self) -> Self {
+        self.writable = true;
+        self
+    }
+
+    /// Only scan `[start, end)`.
+    pub fn range(mut self, start: u64, end: u64) -> Self {
+        self.range = Some((start, end));
+        self
+    }
+
+    /// Only scan regions mapping `path`, a file or a pseudo path like `[heap]`.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    fn regions(&self) -> Result<Vec<(u64, u64)>, HostError> {
+        let selected = |r: &MapRegion| {
+            r.perms.read
+                && (!self.writable || r.perms.write)
+                && (self.path.is_none() || r.path == self.path)
+        };
+        let mut ranges = Vec::new();
+        for region in self.owner.maps()?.iter().filter(|r| selected(r)) {
+            let (start, end) = match self.range {
+                Some((start, end)) => (region.start.max(start), region.end.min(end)),
+                None => (region.start, region.end),
+            };
+            if start < end && region.path.as_deref() != Some("[vvar]") {
+                ranges.push((start, end));
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Call `f` with each chunk of the selected regions and its address. Consecutive chunks
+    /// overlap by `overlap` bytes so that matches crossing a chunk boundary are seen once.
+    fn for_each_chunk<F>(&self, overlap: usize, mut f: F) -> Result<(), HostError>
+    where
+        F: FnMut(u64, &[u8]),
+    {
+        for (start, end) in self.regions()? {
+            let mut addr = start;
+            while addr < end {
+                let len = (self.chunk_size + overlap).min((end - addr) as usize);
+                match self.owner.mem_read(addr, len) {
+                    Ok(data) => f(addr, &data),
+                    Err(e) => {
+                        log::debug!("skipping {:#X}-{:#X}: {}", addr, end, e);
+                        break;
+                    }
+                }
+                addr += self.chunk_size as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Addresses of every match of `pattern`, in ascending order.
+    pub fn find_pattern(&self, pattern: &Pattern) -> Result<Vec<u64>, HostError> {
+        let mut hits = Vec::new();
+        let overlap = pattern.len().saturating_sub(1);
+        self.for_each_chunk(overlap, |addr, data| {
+            // matches starting in the overlap belong to the next chunk
+            let starts = data.len().min(self.chunk_size);
+            hits.extend(
+                pattern
+                    .find_all(data)
+                    .into_iter()
+                    .filter(|&i| i < starts)
+                    .map(|i| addr + i as u64)
+                    .filter(|a| a % self.align == 0),
+            );
+        })?;
+        Ok(hits)
+    }
+
+    pub fn find_bytes(&self, bytes: &[u8]) -> Result<Vec<u64>, HostError> {
+        self.find_pattern(&Pattern::exact(bytes))
+    }
+
+    pub fn find_str(&self, s: &str) -> Result<Vec<u64>, HostError> {
+        self.find_bytes(s.as_bytes())
+    }
+
+    /// Every location holding `value`, as a scan that can be narrowed by `ValueScan::rescan`.
+    pub fn find_value<T: Pod + PartialOrd>(&self, value: T) -> Result<ValueScan<'a, T>, HostError> {
+        let hits = self
+            .find_bytes(bytes_of(&value))?
+            .into_iter()
+            .map(|addr| Hit { addr, value })
+            .collect();
+        Ok(ValueScan {
+            owner: self.owner,
+            chunk_size: self.chunk_size,
+            hits,
+        })
+    }
+}
+
+/// Locations of a typed value, narrowed on each rescan by how their value changed.
+#[derive(Clone)]
+pub struct ValueScan<'a, T> {
+    owner: &'a UProc,
+    chunk_size: usize,
+    hits: Vec<Hit<T>>,
+}
+
+impl<'a, T: Pod + PartialOrd> ValueScan<'a, T> {
+    pub fn hits(&self) -> &[Hit<T>] {
+        &self.hits
+    }
+
+    pub fn addrs(&self) -> Vec<u64> {
+        self.hits.iter().map(|hit| hit.addr).collect()
+    }
+
+    /// Read every hit again, keep the ones passing `filter` and remember their new value.
+    /// Hits close to each other are read together, hits no longer mapped are dropped.
+    pub fn rescan(&mut self, filter: ScanFilter<T>) -> Result<usize, HostError> {
+        let size = size_of::<T>();
+        let mut kept = Vec::new();
+        let mut i = 0;
+        while i < self.hits.len() {
+            let start = self.hits[i].addr;
+            let mut j = i + 1;
+            while j < self.hits.len()
+                && self.hits[j].addr + size as u64 - start <= self.chunk_size as u64
+            {
+                j += 1;
+            }
+            let end = self.hits[j - 1].addr + size as u64;
+
+            match self.owner.mem_read(start, (end - start) as usize) {
+                Ok(data) => {
+                    for hit in &self.hits[i..j] {
+                        let offset = (hit.addr - start) as usize;
+                        let value: T = from_bytes(&data[offset..offset + size]);
+                        if filter.keep(&hit.value, &value) {
+                            kept.push(Hit {
+                                addr: hit.addr,
+                                value,
+                            });
+                        }
+                    }
+                }
+                Err(e) => log::debug!("dropping hits at {:#X}-{:#X}: {}", start, end, e),
+            }
+            i = j;
+        }
+        self.hits = kept;
+        Ok(self.hits.len())
+    }
+}
+
+impl UProc {
+    /// Scanner over every readable region, narrowed with its builder methods.
+    pub fn scanner(&self) -> Scanner<'_> {
+        Scanner {
+            owner: self,
+            chunk_size: CHUNK_SIZE,
+            align: 1,
+            writable: false,
+            range: None,
+            path: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pattern() {
+        let pattern: Pattern = "48 8b ?? 05 ?".parse().unwrap();
+        assert_eq!(pattern.len(), 5);
+        assert_eq!(pattern.to_string(), "48 8b ?? 05 ??");
+        assert!("48 8".parse::<Pattern>().is_err());
+        assert!("zz".parse::<Pattern>().is_err());
+        assert!("48 +f".parse::<Pattern>().is_err());
+        assert!("".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn find_wildcards() {
+        let pattern: Pattern = "aa ?? cc".parse().unwrap();
+        let data = [0xaa, 0x00, 0xcc, 0xaa, 0xbb, 0xcc, 0xaa, 0xcc];
+        assert_eq!(pattern.find_all(&data), [0, 3]);
+        assert!(pattern.find_all(&data[..2]).is_empty());
+
+        let overlapping = Pattern::exact(&[1, 1]);
+        assert_eq!(overlapping.find_all(&[1, 1, 1]), [0, 1]);
+    }
+
+    #[test]
+    fn filters() {
+        assert!(ScanFilter::Changed.keep(&1, &2));
+        assert!(!ScanFilter::Unchanged.keep(&1, &2));
+        assert!(ScanFilter::Increased.keep(&1, &2));
+        assert!(!ScanFilter::Decreased.keep(&1, &2));
+        assert!(ScanFilter::Equal(7).keep(&1, &7));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{breakpoint::Halt, HostError, UProc};
+
+/// Longest x86 instruction, a call returns at most this far past its start.
+const MAX_INST_LEN: u64 = 15;
+
+impl UProc {
+    /// Execute one instruction of the current thread, stepping over the breakpoint it may be
+    /// stopped on, and return the new instruction pointer. The other threads stay stopped.
+    pub fn step_instruction(&self) -> Result<u64, HostError> {
+        let ip = self.registers()?.ip();
+        if self.breakpoints.borrow().contains_key(&ip) {
+            self.step_over_breakpoint()?;
+        } else {
+            self.sstep()?;
+        }
+        Ok(self.registers()?.ip())
+    }
+
+    /// Execute one instruction of the current thread like `step_instruction`, and when it is a
+    /// call, let every thread run until the callee returns. Stops early like `cont` when a
+    /// thread hits another breakpoint or receives a signal.
+    pub fn step_over(&self) -> Result<Halt, HostError> {
+        let tid = self.current_thread();
+        let before = self.registers()?;
+        let addr = self.step_instruction()?;
+
+        // a call pushed the address of the next instruction
+        let word = self.arch().word_size();
+        let sp = self.registers()?.sp();
+        let ret = if sp == before.sp() - word as u64 {
+            let mut buf = [0u8; 8];
+            buf[..word].copy_from_slice(&self.mem_read(sp, word)?);
+            Some(u64::from_le_bytes(buf))
+        } else {
+            None
+        };
+        let ret = match ret {
+            Some(ret) if ret > before.ip() && ret - before.ip() <= MAX_INST_LEN => ret,
+            _ => return Ok(Halt::Stepped { addr }),
+        };
+
+        let temporary = !self.breakpoints.borrow().contains_key(&ret);
+        if temporary {
+            self.set_breakpoint(ret)?;
+        }
+        let res = loop {
+            match self.cont() {
+                // other threads and recursive calls hit it too, deeper in the stack
+                Ok(halt @ Halt::Breakpoint { tid: hit, addr }) if addr == ret => {
+                    if hit == tid && self.registers()?.sp() > sp {
+                        break Ok(Halt::Stepped { addr });
+                    }
+                    if !temporary {
+                        break Ok(halt);
+                    }
+                }
+                res => break res,
+            }
+        };
+        if temporary {
+            let removed = self.remove_breakpoint(ret);
+            return res.and_then(|halt| removed.map(|_| halt));
+        }
+        res
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{backtrace::Cfi, HostError, MapRegion, UProc};
+use nix::unistd::Pid;
+use object::{Object, ObjectSegment, ObjectSymbol, SymbolKind};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// demangled name, without the Rust hash suffix
+    pub name: String,
+    /// name as found in the symbol table
+    pub raw_name: String,
+    /// runtime address in the tracee
+    pub addr: u64,
+    pub size: u64,
+}
+
+/// An ELF file mapped in the tracee.
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub path: String,
+    /// lowest and highest mapped address
+    pub start: u64,
+    pub end: u64,
+    /// difference between runtime addresses and the addresses in the file, non zero for PIE
+    /// executables and shared libraries
+    pub bias: u64,
+    /// sorted by address
+    symbols: Vec<Symbol>,
+    /// call frame information to unwind through the module's functions
+    cfi: Option<Cfi>,
+}
+
+impl Module {
+    fn load(pid: Pid, path: &str, regions: &[&MapRegion]) -> Result<Self, HostError> {
+        // through the tracee's root, the path may only exist in its mount namespace
+        let data = std::fs::read(format!("/proc/{}/root{}", pid, path))?;
+        let elf = object::File::parse(&*data).map_err(|e| HostError::Elf(path.into(), e))?;
+
+        let first = regions[0];
+        let bias = elf
+            .segments()
+            .find_map(|seg| {
+                let (offset, size) = seg.file_range();
+                let inside = offset <= first.offset && first.offset < offset + size;
+                // wraps for modules mapped below their link address, like prelinked ones
+                let linked = seg
+                    .address()
+                    .wrapping_sub(offset)
+                    .wrapping_add(first.offset);
+                inside.then(|| first.start.wrapping_sub(linked))
+            })
+            .unwrap_or(0);
+
+        let mut symbols: Vec<Symbol> = elf
+            .symbols()
+            .chain(elf.dynamic_symbols())
+            .filter(|sym| sym.is_definition() && sym.address() != 0)
+            .filter(|sym| matches!(sym.kind(), SymbolKind::Text | SymbolKind::Data))
+            .filter_map(|sym| {
+                let raw_name = sym.name().ok()?;
+                Some(Symbol {
+                    name: format!("{:#}", rustc_demangle::demangle(raw_name)),
+                    raw_name: raw_name.to_string(),
+                    addr: sym.address().wrapping_add(bias),
+                    size: sym.size(),
+                })
+            })
+            .collect();
+        symbols.sort_by(|a, b| a.addr.cmp(&b.addr).then_with(|| a.name.cmp(&b.name)));
+        symbols.dedup_by(|a, b| a.addr == b.addr && a.raw_name == b.raw_name);
+
+        Ok(Self {
+            path: path.to_string(),
+            start: regions.iter().map(|r| r.start).min().unwrap_or(0),
+            end: regions.iter().map(|r| r.end).max().unwrap_or(0),
+            bias,
+            symbols,
+            cfi: Cfi::load(&elf),
+        })
+    }
+
+    /// File name of the module.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub(crate) fn cfi(&self) -> Option<&Cfi> {
+        self.cfi.as_ref()
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .find(|sym| sym.name == name || sym.raw_name == name)
+    }
+
+    /// The symbol covering `addr`, symbols without a size cover up to the next one.
+    pub fn symbol_at(&self, addr: u64) -> Option<&Symbol> {
+        let i = self.symbols.partition_point(|sym| sym.addr <= addr);
+        let sym = self.symbols[..i].last()?;
+        (sym.size == 0 || addr < sym.addr + sym.size).then_some(sym)
+    }
+}
+
+/// A symbolized address: the module it belongs to and the symbol covering it, with the offset
+/// from the symbol, or from the module start without one.
+#[derive(Debug, Clone, Copy)]
+pub struct Location<'a> {
+    pub module: &'a Module,
+    pub symbol: Option<&'a Symbol>,
+    pub offset: u64,
+}
+
+impl<'a> fmt::Display for Location<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.symbol {
+            Some(sym) => write!(f, "{}!{}+{:#x}", self.module.name(), sym.name, self.offset),
+            None => write!(f, "{}+{:#x}", self.module.name(), self.offset),
+        }
+    }
+}
+
+/// Symbols of every ELF module mapped in a tracee, at the time it was built.
+#[derive(Debug, Clone)]
+pub struct Symbols {
+    modules: Vec<Module>,
+}
+
+impl Symbols {
+    pub fn new(pid: Pid, maps: &[MapRegion]) -> Result<Self, HostError> {
+        let mut paths: Vec<&str> = Vec::new();
+        for region in maps.iter().filter(|r| r.is_file()) {
+            let path = region.path.as_deref().unwrap_or_default();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+
+        let mut modules = Vec::new();
+        for path in paths {
+            let regions: Vec<&MapRegion> = maps
+                .iter()
+                .filter(|r| r.path.as_deref() == Some(path))
+                .collect();
+            match Module::load(pid, path, &regions) {
+                Ok(module) => modules.push(module),
+                // mapped files that are not ELF, like locale archives
+                Err(e) => log::debug!("skipping {}: {}", path, e),
+            }
+        }
+        Ok(Self { modules })
+    }
+
+    pub fn modules(&self) -> &[Module] {
+        &self.modules
+    }
+
+    /// Module by file name or full path.
+    pub fn module(&self, name: &str) -> Option<&Module> {
+        self.modules
+            .iter()
+            .find(|m| m.path == name || m.name() == name)
+    }
+
+    /// Runtime address of a symbol, by demangled or raw name, searching modules in load order.
+    pub fn resolve(&self, name: &str) -> Option<u64> {
+        self.modules
+            .iter()
+            .find_map(|m| m.resolve(name))
+            .map(|sym| sym.addr)
+    }
+
+    pub fn symbolize(&self, addr: u64) -> Option<Location<'_>> {
+        let module = self.modules.iter().find(|m| m.contains(addr))?;
+        let symbol = module.symbol_at(addr);
+        let offset = addr - symbol.map_or(module.start, |sym| sym.addr);
+        Some(Location {
+            module,
+            symbol,
+            offset,
+        })
+    }
+}
+
+impl UProc {
+    /// Load the symbols of every module currently mapped.
+    pub fn symbols(&self) -> Result<Symbols, HostError> {
+        Symbols::new(self.pid, &self.maps()?)
+    }
+}
@@ -0,0 +1,111 @@
+use crate::HostError;
+use nix::errno::Errno;
+use std::fmt;
+use syscalls::Sysno;
+
+/// Return value of a syscall as left in `rax`: the result, or `-errno` in `-4095..=-1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallResult(pub i64);
+
+impl SyscallResult {
+    pub fn from_raw(rax: u64) -> Self {
+        Self(rax as i64)
+    }
+
+    pub fn errno(self) -> Option<Errno> {
+        match self.0 {
+            -4095..=-1 => Some(Errno::from_i32(-self.0 as i32)),
+            _ => None,
+        }
+    }
+
+    pub fn value(self) -> Result<u64, Errno> {
+        match self.errno() {
+            Some(errno) => Err(errno),
+            None => Ok(self.0 as u64),
+        }
+    }
+
+    /// Whether the syscall was interrupted and the kernel restarts it when the thread returns
+    /// to user space: ERESTARTSYS, ERESTARTNOINTR, ERESTARTNOHAND or ERESTART_RESTARTBLOCK.
+    /// Tracers see these only while the thread is stopped inside the syscall.
+    pub fn is_restart(self) -> bool {
+        matches!(self.0, -512 | -513 | -514 | -516)
+    }
+
+    /// `value` with the failure turned into `HostError::Syscall`.
+    pub fn check(self, sysno: Sysno) -> Result<u64, HostError> {
+        self.value()
+            .map_err(|errno| HostError::Syscall { sysno, errno })
+    }
+}
+
+impl fmt::Display for SyscallResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.errno() {
+            _ if self.is_restart() => write!(f, "? ERESTART ({})", -self.0),
+            Some(errno) => write!(f, "-1 {:?} ({})", errno, errno.desc()),
+            None if (0..=0xFFFF).contains(&self.0) => write!(f, "{}", self.0),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        assert_eq!(SyscallResult::from_raw(0).value(), Ok(0));
+        assert_eq!(
+            SyscallResult::from_raw(0x7f00_0000_0000).value(),
+            Ok(0x7f00_0000_0000)
+        );
+        assert_eq!(
+            SyscallResult::from_raw(-22i64 as u64).value(),
+            Err(Errno::EINVAL)
+        );
+        assert_eq!(
+            SyscallResult::from_raw(-4095i64 as u64).errno(),
+            Some(Errno::from_i32(4095))
+        );
+        // large addresses above the errno range are not errors
+        assert_eq!(SyscallResult::from_raw(-4096i64 as u64).errno(), None);
+    }
+
+    #[test]
+    fn check() {
+        assert!(matches!(
+            SyscallResult(-12).check(Sysno::mmap),
+            Err(HostError::Syscall {
+                sysno: Sysno::mmap,
+                errno: Errno::ENOMEM
+            })
+        ));
+        assert_eq!(SyscallResult(3).check(Sysno::dup).unwrap(), 3);
+    }
+
+    #[test]
+    fn restart() {
+        assert!(SyscallResult(-516).is_restart());
+        assert!(SyscallResult(-512).is_restart());
+        // ENOIOCTLCMD sits between the restart codes
+        assert!(!SyscallResult(-515).is_restart());
+        assert!(!SyscallResult(-4).is_restart());
+        assert_eq!(SyscallResult(-516).to_string(), "? ERESTART (516)");
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(SyscallResult(14).to_string(), "14");
+        assert_eq!(
+            SyscallResult(0x7f00_0000_0000).to_string(),
+            "0x7f0000000000"
+        );
+        assert_eq!(
+            SyscallResult(-2).to_string(),
+            "-1 ENOENT (No such file or directory)"
+        );
+    }
+}
@@ -0,0 +1,566 @@
+use crate::{HostError, UProc, Watchpoint};
+use nix::{
+    errno::Errno,
+    libc,
+    sys::{
+        ptrace,
+        signal::Signal::{self, SIGSTOP, SIGTRAP, SIGTSTP, SIGTTIN, SIGTTOU},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
+use std::{cell::RefCell, collections::VecDeque};
+
+thread_local! {
+    /// Wait statuses reaped on this thread that did not belong to the waiting `UProc`:
+    /// tracees of another `UProc`, or new threads reporting before their clone event.
+    static STRAY: RefCell<Vec<WaitStatus>> = const { RefCell::new(Vec::new()) };
+}
+
+fn take_stray<F: Fn(Pid) -> bool>(f: F) -> Option<WaitStatus> {
+    STRAY.with(|stray| {
+        let mut stray = stray.borrow_mut();
+        let i = stray.iter().position(|s| s.pid().is_some_and(&f))?;
+        Some(stray.remove(i))
+    })
+}
+
+fn task_ids(pid: Pid) -> Result<Vec<Pid>, HostError> {
+    let mut tids = Vec::new();
+    for entry in std::fs::read_dir(format!("/proc/{}/task", pid))? {
+        if let Some(tid) = entry?.file_name().to_str().and_then(|s| s.parse().ok()) {
+            tids.push(Pid::from_raw(tid));
+        }
+    }
+    tids.sort();
+    Ok(tids)
+}
+
+fn tgkill(pid: Pid, tid: Pid, signal: Signal) -> Result<(), HostError> {
+    // SAFETY: tgkill takes no pointers
+    let res = unsafe { libc::syscall(libc::SYS_tgkill, pid.as_raw(), tid.as_raw(), signal as i32) };
+    Errno::result(res)?;
+    Ok(())
+}
+
+/// What happens to a signal stopping a thread in a signal-delivery stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalPolicy {
+    /// deliver it to the tracee the next time the thread is resumed
+    Deliver,
+    /// discard it, as if it was never sent
+    Suppress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// signal-delivery stop, SIGTRAP for breakpoints and single steps. Other signals are
+    /// delivered when the thread is resumed, unless their policy suppresses them.
+    Signal(Signal),
+    /// the thread entered the group-stop of a stopping signal, it resumes without it
+    GroupStop(Signal),
+    /// the stop requested by `interrupt` or `stop_all`, reported late because the thread
+    /// stopped for something else first
+    Interrupted,
+    /// SIGTRAP right after the thread accessed the address of a watchpoint
+    Watchpoint(Watchpoint),
+    /// the thread created a new thread, which is traced and stopped
+    NewThread(Pid),
+    /// the thread forked the process `child`, which is traced and stopped until taken with
+    /// `take_child`. A vfork parent waits until the child execs or exits.
+    Fork {
+        child: Pid,
+        vfork: bool,
+    },
+    /// the process executed a new program, by the thread that had the id `former_tid`.
+    /// Breakpoints and watchpoints went away with the old program.
+    Exec {
+        former_tid: Pid,
+    },
+    /// the thread is about to exit with this wait status, its memory and registers can still
+    /// be read
+    Exiting(i32),
+    /// syscall entry or exit
+    Syscall,
+    /// any other PTRACE_EVENT_*
+    Event(i32),
+    Exited(i32),
+    Killed(Signal),
+}
+
+/// Which thread stopped and why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stop {
+    pub tid: Pid,
+    pub reason: StopReason,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Thread {
+    running: bool,
+    /// a SIGSTOP or PTRACE_INTERRUPT was sent and the thread has not reported it yet
+    stop_requested: bool,
+    /// signals to deliver on the next resumes, one per resume
+    deferred: VecDeque<Signal>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Resume {
+    Continue,
+    Syscall,
+}
+
+impl UProc {
+    pub fn threads(&self) -> Vec<Pid> {
+        self.threads.borrow().keys().copied().collect()
+    }
+
+    /// Thread used for registers, single steps and syscall injection.
+    pub fn current_thread(&self) -> Pid {
+        self.current.get()
+    }
+
+    pub fn select_thread(&self, tid: Pid) -> Result<(), HostError> {
+        if !self.threads.borrow().contains_key(&tid) {
+            return Err(HostError::NoSuchThread(tid));
+        }
+        self.current.set(tid);
+        Ok(())
+    }
+
+    /// Attach every thread of the process besides the leader, until no new ones show up.
+    pub(crate) fn attach_threads(&self) -> Result<(), HostError> {
+        loop {
+            let mut attached = false;
+            for tid in task_ids(self.pid)? {
+                if self.threads.borrow().contains_key(&tid) {
+                    continue;
+                }
+                match self.attach_thread(tid) {
+                    Err(HostError::NixError(Errno::ESRCH)) => continue,
+                    res => res?,
+                }
+                attached = true;
+            }
+            if !attached {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Attach `tid` and wait for it to stop. PTRACE_ATTACH stops it with a SIGSTOP, a seized
+    /// thread is stopped with PTRACE_INTERRUPT instead, which the tracee cannot observe. A
+    /// thread stopping for something else first keeps that stop for `wait_any`.
+    pub(crate) fn attach_thread(&self, tid: Pid) -> Result<(), HostError> {
+        if self.seized {
+            ptrace::seize(
+                tid,
+                self.options.get() | ptrace::Options::PTRACE_O_TRACECLONE,
+            )?;
+            self.add_thread(tid);
+            self.request_stop(tid)?;
+        } else {
+            ptrace::attach(tid)?;
+            self.add_thread(tid);
+            self.set_stop_requested(tid, true);
+        }
+
+        let status = self.wait_tid(tid)?;
+        if !self.take_stop_request(tid, &status) {
+            let reason = self.stop_reason(tid, status)?;
+            self.pending.borrow_mut().push_back(Stop { tid, reason });
+        }
+        Ok(())
+    }
+
+    /// Set how signals stopping a thread are handled. Every signal is delivered by default,
+    /// except SIGSTOP in attached processes, where the tracer itself uses it to stop threads.
+    /// Seized processes are stopped without signals and get SIGSTOP delivered too.
+    pub fn set_signal_policy(&self, signal: Signal, policy: SignalPolicy) {
+        self.signal_policy.borrow_mut().insert(signal, policy);
+    }
+
+    pub fn signal_policy(&self, signal: Signal) -> SignalPolicy {
+        match self.signal_policy.borrow().get(&signal) {
+            Some(policy) => *policy,
+            None if signal == SIGSTOP && !self.seized => SignalPolicy::Suppress,
+            None => SignalPolicy::Deliver,
+        }
+    }
+
+    /// Keep `signal` for the next resume of `tid`, unless its policy suppresses it.
+    pub(crate) fn defer_signal(&self, tid: Pid, signal: Signal) {
+        if self.signal_policy(signal) == SignalPolicy::Suppress {
+            log::debug!("tid: {} suppressed {}", tid, signal);
+            return;
+        }
+        if let Some(thread) = self.threads.borrow_mut().get_mut(&tid) {
+            thread.deferred.push_back(signal);
+        }
+    }
+
+    /// Next signal to deliver to `tid`.
+    pub(crate) fn take_deferred(&self, tid: Pid) -> Option<Signal> {
+        self.threads
+            .borrow_mut()
+            .get_mut(&tid)
+            .and_then(|thread| thread.deferred.pop_front())
+    }
+
+    pub(crate) fn add_thread(&self, tid: Pid) {
+        self.threads.borrow_mut().insert(tid, Thread::default());
+    }
+
+    /// Drop the last deferred `signal` of `tid`, raised by code the tracer made it run.
+    pub(crate) fn discard_deferred(&self, tid: Pid, signal: Signal) {
+        if let Some(thread) = self.threads.borrow_mut().get_mut(&tid) {
+            if let Some(i) = thread.deferred.iter().rposition(|s| *s == signal) {
+                thread.deferred.remove(i);
+            }
+        }
+    }
+
+    pub(crate) fn set_running(&self, tid: Pid, running: bool) {
+        if let Some(thread) = self.threads.borrow_mut().get_mut(&tid) {
+            thread.running = running;
+        }
+    }
+
+    pub(crate) fn is_running(&self, tid: Pid) -> bool {
+        self.threads
+            .borrow()
+            .get(&tid)
+            .is_some_and(|thread| thread.running)
+    }
+
+    fn set_stop_requested(&self, tid: Pid, requested: bool) {
+        if let Some(thread) = self.threads.borrow_mut().get_mut(&tid) {
+            thread.stop_requested = requested;
+        }
+    }
+
+    /// Ask a running thread to stop, with PTRACE_INTERRUPT when seized and SIGSTOP otherwise.
+    fn request_stop(&self, tid: Pid) -> Result<(), HostError> {
+        let res = if self.seized {
+            ptrace::interrupt(tid).map_err(HostError::from)
+        } else {
+            tgkill(self.pid, tid, SIGSTOP)
+        };
+        match res {
+            // the thread is gone, its exit is reported by the next wait
+            Err(HostError::NixError(Errno::ESRCH)) => Ok(()),
+            res => {
+                self.set_stop_requested(tid, true);
+                res
+            }
+        }
+    }
+
+    /// Whether `status` is the stop requested from `tid`, which is then no longer pending.
+    fn take_stop_request(&self, tid: Pid, status: &WaitStatus) -> bool {
+        let requested = match status {
+            WaitStatus::Stopped(_, SIGSTOP) => !self.seized,
+            WaitStatus::PtraceEvent(_, SIGTRAP, event) => {
+                self.seized && *event == ptrace::Event::PTRACE_EVENT_STOP as i32
+            }
+            _ => false,
+        };
+        match self.threads.borrow_mut().get_mut(&tid) {
+            Some(thread) if requested && thread.stop_requested => {
+                thread.stop_requested = false;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Wait for a stop of one specific thread.
+    pub(crate) fn wait_tid(&self, tid: Pid) -> Result<WaitStatus, HostError> {
+        let status = match take_stray(|p| p == tid) {
+            Some(status) => status,
+            None => waitpid(tid, Some(WaitPidFlag::__WALL))?,
+        };
+        self.set_running(tid, false);
+        Ok(status)
+    }
+
+    /// Wait until any thread of the tracee stops. Stops collected while stopping all threads
+    /// are returned first.
+    pub fn wait_any(&self) -> Result<Stop, HostError> {
+        self.wait_any_with(WaitPidFlag::empty())
+            .map(|stop| stop.expect("blocking wait returns a stop"))
+    }
+
+    /// `wait_any` without blocking, `None` when no thread has stopped.
+    pub(crate) fn try_wait_any(&self) -> Result<Option<Stop>, HostError> {
+        self.wait_any_with(WaitPidFlag::WNOHANG)
+    }
+
+    fn wait_any_with(&self, flags: WaitPidFlag) -> Result<Option<Stop>, HostError> {
+        if let Some(stop) = self.pending.borrow_mut().pop_front() {
+            return Ok(Some(stop));
+        }
+
+        let flags = flags | WaitPidFlag::__WALL | WaitPidFlag::__WNOTHREAD;
+        loop {
+            let status = match take_stray(|p| self.threads.borrow().contains_key(&p)) {
+                Some(status) => status,
+                None => waitpid(None, Some(flags))?,
+            };
+            let tid = match status {
+                WaitStatus::StillAlive => return Ok(None),
+                status => match status.pid() {
+                    Some(tid) if self.threads.borrow().contains_key(&tid) => tid,
+                    Some(_) => {
+                        STRAY.with(|stray| stray.borrow_mut().push(status));
+                        continue;
+                    }
+                    None => continue,
+                },
+            };
+            self.set_running(tid, false);
+            return self
+                .stop_reason(tid, status)
+                .map(|reason| Some(Stop { tid, reason }));
+        }
+    }
+
+    /// Resume a thread that stopped on its own without anything to report: signals are
+    /// delivered, new threads run, and breakpoints are stepped over. Fails with
+    /// `HostError::ProcessExited` when the process is gone.
+    pub(crate) fn keep_running(&self, stop: Stop) -> Result<(), HostError> {
+        let tid = stop.tid;
+        match stop.reason {
+            StopReason::Signal(SIGTRAP) => {
+                if self.rewind_breakpoint(tid)?.is_some() {
+                    let current = self.current.replace(tid);
+                    let stepped = self.step_over_breakpoint();
+                    self.current.set(current);
+                    stepped?;
+                    self.resume_thread(tid, None, Resume::Continue)?;
+                } else {
+                    self.resume_thread(tid, Some(SIGTRAP), Resume::Continue)?;
+                }
+            }
+            StopReason::NewThread(child) => {
+                self.resume_thread(tid, None, Resume::Continue)?;
+                self.resume_thread(child, None, Resume::Continue)?;
+            }
+            // nobody asked for it, the dropped child is detached
+            StopReason::Fork { child, .. } => {
+                drop(self.take_child(child));
+                self.resume_thread(tid, None, Resume::Continue)?;
+            }
+            StopReason::Exited(_) | StopReason::Killed(_) if tid == self.pid => {
+                return Err(HostError::ProcessExited(stop));
+            }
+            StopReason::Exited(_) | StopReason::Killed(_) => {}
+            StopReason::Signal(_)
+            | StopReason::GroupStop(_)
+            | StopReason::Interrupted
+            | StopReason::Watchpoint(_)
+            | StopReason::Exec { .. }
+            | StopReason::Exiting(_)
+            | StopReason::Syscall
+            | StopReason::Event(_) => self.resume_thread(tid, None, Resume::Continue)?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn stop_reason(
+        &self,
+        tid: Pid,
+        status: WaitStatus,
+    ) -> Result<StopReason, HostError> {
+        let reason = match status {
+            WaitStatus::Exited(_, code) => StopReason::Exited(code),
+            WaitStatus::Signaled(_, signal, _) => StopReason::Killed(signal),
+            // a group-stop has no siginfo
+            WaitStatus::Stopped(_, signal @ (SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU))
+                if ptrace::getsiginfo(tid) == Err(Errno::EINVAL) =>
+            {
+                StopReason::GroupStop(signal)
+            }
+            // seized threads report group-stops as PTRACE_EVENT_STOP with the stopping signal
+            WaitStatus::PtraceEvent(_, signal, event)
+                if event == ptrace::Event::PTRACE_EVENT_STOP as i32 && signal != SIGTRAP =>
+            {
+                StopReason::GroupStop(signal)
+            }
+            status if self.take_stop_request(tid, &status) => StopReason::Interrupted,
+            WaitStatus::PtraceEvent(_, SIGTRAP, event)
+                if event == ptrace::Event::PTRACE_EVENT_STOP as i32 =>
+            {
+                StopReason::Interrupted
+            }
+            WaitStatus::Stopped(_, SIGTRAP) => match self.take_watchpoint_hit(tid)? {
+                Some(watchpoint) => StopReason::Watchpoint(watchpoint),
+                None => StopReason::Signal(SIGTRAP),
+            },
+            WaitStatus::Stopped(_, signal) => {
+                self.defer_signal(tid, signal);
+                StopReason::Signal(signal)
+            }
+            WaitStatus::PtraceSyscall(_) => StopReason::Syscall,
+            WaitStatus::PtraceEvent(_, _, event)
+                if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 =>
+            {
+                let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                self.add_thread(child);
+                self.wait_tid(child)?;
+                // debug registers are not inherited
+                self.apply_watchpoints(child)?;
+                log::debug!("pid: {} new thread: {}", self.pid, child);
+                StopReason::NewThread(child)
+            }
+            WaitStatus::PtraceEvent(_, _, event)
+                if event == ptrace::Event::PTRACE_EVENT_FORK as i32
+                    || event == ptrace::Event::PTRACE_EVENT_VFORK as i32 =>
+            {
+                let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                let vfork = event == ptrace::Event::PTRACE_EVENT_VFORK as i32;
+                self.add_child(child, vfork)?;
+                StopReason::Fork { child, vfork }
+            }
+            WaitStatus::PtraceEvent(_, _, event)
+                if event == ptrace::Event::PTRACE_EVENT_EXEC as i32 =>
+            {
+                let former_tid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                self.reset_after_exec(former_tid)?;
+                StopReason::Exec { former_tid }
+            }
+            WaitStatus::PtraceEvent(_, _, event)
+                if event == ptrace::Event::PTRACE_EVENT_EXIT as i32 =>
+            {
+                StopReason::Exiting(ptrace::getevent(tid)? as i32)
+            }
+            WaitStatus::PtraceEvent(_, _, event) => StopReason::Event(event),
+            status => return Err(HostError::UnexpectedWaitStatus(status)),
+        };
+
+        if matches!(reason, StopReason::Exited(_) | StopReason::Killed(_)) {
+            self.threads.borrow_mut().remove(&tid);
+            if self.current.get() == tid {
+                self.current.set(self.pid);
+            }
+        }
+        Ok(reason)
+    }
+
+    /// Resume `tid`, delivering `signal` or else the next deferred one.
+    pub(crate) fn resume_thread(
+        &self,
+        tid: Pid,
+        signal: Option<Signal>,
+        how: Resume,
+    ) -> Result<(), HostError> {
+        let signal = signal.or_else(|| self.take_deferred(tid));
+        let res = match how {
+            Resume::Continue => ptrace::cont(tid, signal),
+            Resume::Syscall => ptrace::syscall(tid, signal),
+        };
+        match res {
+            // the thread is gone, its exit is reported by the next wait
+            Ok(()) | Err(Errno::ESRCH) => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.set_running(tid, true);
+        Ok(())
+    }
+
+    pub(crate) fn resume_all_with(&self, how: Resume) -> Result<(), HostError> {
+        let stopped: Vec<Pid> = self
+            .threads
+            .borrow()
+            .iter()
+            .filter(|(_, thread)| !thread.running)
+            .map(|(tid, _)| *tid)
+            .filter(|tid| !self.pending.borrow().iter().any(|stop| stop.tid == *tid))
+            .collect();
+        for tid in stopped {
+            self.resume_thread(tid, None, how)?;
+        }
+        Ok(())
+    }
+
+    /// Resume every stopped thread, except the ones with a stop not yet returned by `wait_any`,
+    /// delivering the signals they stopped for.
+    pub fn resume_all(&self) -> Result<(), HostError> {
+        self.resume_all_with(Resume::Continue)
+    }
+
+    /// Stop every thread of the process. A seized process is stopped with PTRACE_INTERRUPT,
+    /// which it cannot observe, an attached one with SIGSTOPs that are never delivered.
+    pub fn interrupt(&self) -> Result<(), HostError> {
+        self.stop_all()
+    }
+
+    /// Let every thread stopped by `interrupt` run again, stepping the current one over the
+    /// breakpoint it may be stopped on.
+    pub fn resume(&self) -> Result<(), HostError> {
+        self.step_over_breakpoint()?;
+        self.resume_all()
+    }
+
+    /// Stop every running thread. Threads that stop for another reason first keep that stop
+    /// for `wait_any`, except breakpoint hits, which are rewound to be hit again on resume.
+    /// Their requested stop is reported later as `StopReason::Interrupted`.
+    pub fn stop_all(&self) -> Result<(), HostError> {
+        let running: Vec<Pid> = self
+            .threads
+            .borrow()
+            .iter()
+            .filter(|(_, thread)| thread.running)
+            .map(|(tid, _)| *tid)
+            .collect();
+
+        for tid in &running {
+            self.request_stop(*tid)?;
+        }
+        for tid in running {
+            match self.wait_tid(tid)? {
+                status if self.take_stop_request(tid, &status) => {}
+                WaitStatus::Stopped(_, SIGTRAP) if self.rewind_breakpoint(tid)?.is_some() => {}
+                status => {
+                    let reason = self.stop_reason(tid, status)?;
+                    self.pending.borrow_mut().push_back(Stop { tid, reason });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn any_running(&self) -> bool {
+        self.threads.borrow().values().any(|thread| thread.running)
+    }
+
+    /// Run the threads of an attached process that still have a SIGSTOP queued until they
+    /// report it, so that it cannot stop the process once detached. Detaching a seized
+    /// thread cancels its pending PTRACE_INTERRUPT, nothing is left to drain.
+    pub(crate) fn drain_stop_requests(&self) -> Result<(), HostError> {
+        if self.seized {
+            return Ok(());
+        }
+        let requested: Vec<Pid> = self
+            .threads
+            .borrow()
+            .iter()
+            .filter(|(_, thread)| thread.stop_requested)
+            .map(|(tid, _)| *tid)
+            .collect();
+        for tid in requested {
+            loop {
+                self.resume_thread(tid, None, Resume::Continue)?;
+                let status = self.wait_tid(tid)?;
+                match self.stop_reason(tid, status)? {
+                    StopReason::Interrupted | StopReason::Exited(_) | StopReason::Killed(_) => {
+                        break
+                    }
+                    // new threads stay stopped until detached, signals go with the next resume
+                    reason => log::debug!("tid: {} drained {:?}", tid, reason),
+                }
+            }
+        }
+        Ok(())
+    }
+}
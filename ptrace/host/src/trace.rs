@@ -0,0 +1,377 @@
+use crate::{thread::Resume, HostError, StopReason, SyscallResult, UProc};
+use nix::{
+    errno::Errno,
+    libc::{user_regs_struct, AT_FDCWD},
+    sys::ptrace,
+    unistd::Pid,
+};
+use std::{collections::HashMap, fmt, ops::ControlFlow};
+use syscalls::Sysno;
+
+/// Longest string or buffer argument copied out of the tracee.
+const MAX_ARG_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgKind {
+    Int,
+    Hex,
+    Fd,
+    Str,
+    /// buffer filled by the caller, length in the next argument
+    BufIn,
+    /// buffer filled by the kernel, length is the return value
+    BufOut,
+}
+
+/// Arguments of syscalls without a known signature.
+const UNKNOWN: &[ArgKind] = &[ArgKind::Hex; 6];
+
+fn signature(sysno: Sysno) -> &'static [ArgKind] {
+    use ArgKind::*;
+    match sysno {
+        Sysno::read | Sysno::pread64 | Sysno::recvfrom => &[Fd, BufOut, Int],
+        Sysno::write | Sysno::pwrite64 | Sysno::sendto => &[Fd, BufIn, Int],
+        Sysno::open => &[Str, Hex, Hex],
+        Sysno::openat => &[Fd, Str, Hex, Hex],
+        Sysno::close | Sysno::fsync | Sysno::dup => &[Fd],
+        Sysno::dup2 => &[Fd, Fd],
+        Sysno::stat | Sysno::lstat | Sysno::statfs => &[Str, Hex],
+        Sysno::fstat => &[Fd, Hex],
+        Sysno::newfstatat | Sysno::statx => &[Fd, Str, Hex, Hex, Hex],
+        Sysno::access => &[Str, Hex],
+        Sysno::readlink => &[Str, BufOut, Int],
+        Sysno::execve => &[Str, Hex, Hex],
+        Sysno::mmap => &[Hex, Int, Hex, Hex, Fd, Hex],
+        Sysno::munmap => &[Hex, Int],
+        Sysno::mprotect => &[Hex, Int, Hex],
+        Sysno::brk => &[Hex],
+        Sysno::lseek => &[Fd, Int, Int],
+        Sysno::ioctl | Sysno::fcntl => &[Fd, Hex, Hex],
+        Sysno::nanosleep => &[Hex, Hex],
+        Sysno::clock_nanosleep => &[Int, Hex, Hex, Hex],
+        Sysno::clock_gettime => &[Int, Hex],
+        Sysno::futex => &[Hex, Int, Int, Hex, Hex, Int],
+        Sysno::rt_sigaction | Sysno::rt_sigprocmask => &[Int, Hex, Hex, Int],
+        Sysno::kill => &[Int, Int],
+        Sysno::tgkill => &[Int, Int, Int],
+        Sysno::exit | Sysno::exit_group => &[Int],
+        Sysno::getpid | Sysno::gettid | Sysno::getppid | Sysno::sched_yield => &[],
+        _ => UNKNOWN,
+    }
+}
+
+/// A syscall argument decoded according to the syscall's signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyscallArg {
+    Int(i64),
+    Hex(u64),
+    Fd(i32),
+    /// NUL-terminated string, truncated to a few bytes
+    Str(Vec<u8>),
+    /// buffer contents, truncated to a few bytes, and the full length
+    Buf(Vec<u8>, usize),
+}
+
+impl fmt::Display for SyscallArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn escaped(f: &mut fmt::Formatter<'_>, bytes: &[u8], truncated: bool) -> fmt::Result {
+            write!(f, "\"")?;
+            for &b in bytes {
+                write!(f, "{}", std::ascii::escape_default(b))?;
+            }
+            write!(f, "\"{}", if truncated { "..." } else { "" })
+        }
+
+        match self {
+            SyscallArg::Int(v) => write!(f, "{}", v),
+            SyscallArg::Hex(0) => write!(f, "0"),
+            SyscallArg::Hex(v) => write!(f, "{:#x}", v),
+            SyscallArg::Fd(AT_FDCWD) => write!(f, "AT_FDCWD"),
+            SyscallArg::Fd(fd) => write!(f, "{}", fd),
+            SyscallArg::Str(s) => escaped(f, s, s.len() >= MAX_ARG_LEN),
+            SyscallArg::Buf(b, len) => escaped(f, b, b.len() < *len),
+        }
+    }
+}
+
+/// One completed syscall of a tracee thread.
+#[derive(Debug, Clone)]
+pub struct SyscallEvent {
+    pub tid: Pid,
+    /// x86_64 meaning of `nr`, `None` for numbers this build does not know, like newer
+    /// syscalls or -1, and for i386 syscalls without an x86_64 counterpart
+    pub sysno: Option<Sysno>,
+    /// the raw syscall number, in the tracee's architecture
+    pub nr: u64,
+    pub raw_args: [u64; 6],
+    pub args: Vec<SyscallArg>,
+    /// `None` for syscalls that do not return, like `exit_group`
+    pub ret: Option<SyscallResult>,
+}
+
+impl SyscallEvent {
+    pub fn errno(&self) -> Option<Errno> {
+        self.ret.and_then(SyscallResult::errno)
+    }
+}
+
+impl fmt::Display for SyscallEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.sysno {
+            Some(sysno) => write!(f, "{}(", sysno)?,
+            None => write!(f, "syscall_{:#x}(", self.nr)?,
+        }
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ")")?;
+
+        match self.ret {
+            None => write!(f, " = ?"),
+            Some(ret) => write!(f, " = {}", ret),
+        }
+    }
+}
+
+/// Iterator over the syscalls made by all threads of a tracee, driven by PTRACE_SYSCALL.
+/// Ends when the tracee exits. Dropping it stops all threads again.
+pub struct Syscalls<'a> {
+    owner: &'a UProc,
+    /// syscalls entered and not yet exited, per thread
+    entries: HashMap<Pid, SyscallEvent>,
+    /// thread stopped on the last returned event, resumed by the next call
+    resume: Option<Pid>,
+    started: bool,
+    done: bool,
+}
+
+impl UProc {
+    pub fn syscalls(&self) -> Result<Syscalls<'_>, HostError> {
+        self.set_options(ptrace::Options::PTRACE_O_TRACESYSGOOD)?;
+        Ok(Syscalls {
+            owner: self,
+            entries: HashMap::new(),
+            resume: None,
+            started: false,
+            done: false,
+        })
+    }
+
+    /// Report every syscall of the tracee to `f` until it exits or `f` breaks.
+    pub fn trace_syscalls<F>(&self, mut f: F) -> Result<(), HostError>
+    where
+        F: FnMut(&SyscallEvent) -> ControlFlow<()>,
+    {
+        for event in self.syscalls()? {
+            if f(&event?).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_entry(&self, tid: Pid, regs: &user_regs_struct) -> SyscallEvent {
+        let arch = self.arch();
+        let raw_args = arch.syscall_args(regs);
+        let nr = regs.orig_rax;
+        let sysno = arch.sysno(nr);
+        let kinds = sysno.map_or(UNKNOWN, signature);
+
+        let args = kinds
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| match kind {
+                ArgKind::Int => SyscallArg::Int(raw_args[i] as i64),
+                ArgKind::Hex | ArgKind::BufOut => SyscallArg::Hex(raw_args[i]),
+                ArgKind::Fd => SyscallArg::Fd(raw_args[i] as i32),
+                ArgKind::Str => self
+                    .mem_read_cstr(raw_args[i], MAX_ARG_LEN)
+                    .map(SyscallArg::Str)
+                    .unwrap_or(SyscallArg::Hex(raw_args[i])),
+                ArgKind::BufIn => {
+                    let len = raw_args[i + 1] as usize;
+                    self.mem_read(raw_args[i], len.min(MAX_ARG_LEN))
+                        .map(|b| SyscallArg::Buf(b, len))
+                        .unwrap_or(SyscallArg::Hex(raw_args[i]))
+                }
+            })
+            .collect();
+
+        SyscallEvent {
+            tid,
+            sysno,
+            nr,
+            raw_args,
+            args,
+            ret: None,
+        }
+    }
+
+    fn decode_exit(&self, mut event: SyscallEvent, regs: &user_regs_struct) -> SyscallEvent {
+        let ret = self.arch().syscall_result(regs);
+        for (i, kind) in event.sysno.map_or(UNKNOWN, signature).iter().enumerate() {
+            if let (ArgKind::BufOut, Ok(len @ 1..)) = (kind, ret.value()) {
+                if let Ok(b) = self.mem_read(event.raw_args[i], (len as usize).min(MAX_ARG_LEN)) {
+                    event.args[i] = SyscallArg::Buf(b, len as usize);
+                }
+            }
+        }
+        event.ret = Some(ret);
+        event
+    }
+}
+
+impl<'a> Syscalls<'a> {
+    fn step(&mut self) -> Result<Option<SyscallEvent>, HostError> {
+        let owner = self.owner;
+        if !self.started {
+            self.started = true;
+            owner.resume_all_with(Resume::Syscall)?;
+        } else if let Some(tid) = self.resume.take() {
+            owner.resume_thread(tid, None, Resume::Syscall)?;
+        }
+
+        loop {
+            let stop = owner.wait_any()?;
+            let tid = stop.tid;
+            match stop.reason {
+                StopReason::Syscall => {
+                    let regs = ptrace::getregs(tid)?;
+                    let event = match self.entries.remove(&tid) {
+                        None => {
+                            let entry = owner.decode_entry(tid, &regs);
+                            if matches!(entry.sysno, Some(Sysno::exit | Sysno::exit_group)) {
+                                Some(entry)
+                            } else {
+                                self.entries.insert(tid, entry);
+                                None
+                            }
+                        }
+                        Some(entry) => Some(owner.decode_exit(entry, &regs)),
+                    };
+                    if let Some(event) = event {
+                        self.resume = Some(tid);
+                        return Ok(Some(event));
+                    }
+                }
+                // deferred signals are delivered by the resume below
+                StopReason::Signal(_)
+                | StopReason::GroupStop(_)
+                | StopReason::Interrupted
+                | StopReason::Watchpoint(_) => {}
+                StopReason::NewThread(child) => {
+                    owner.resume_thread(child, None, Resume::Syscall)?
+                }
+                // children are not traced, they are detached when dropped
+                StopReason::Fork { child, .. } => drop(owner.take_child(child)),
+                StopReason::Exec { former_tid } => {
+                    if let Some(entry) = self.entries.remove(&former_tid) {
+                        self.entries.insert(tid, SyscallEvent { tid, ..entry });
+                    }
+                }
+                StopReason::Exiting(_) | StopReason::Event(_) => {}
+                StopReason::Exited(_) | StopReason::Killed(_) if tid == owner.pid => {
+                    return Ok(None)
+                }
+                StopReason::Exited(_) | StopReason::Killed(_) => {
+                    self.entries.remove(&tid);
+                    continue;
+                }
+            }
+            owner.resume_thread(tid, None, Resume::Syscall)?;
+        }
+    }
+}
+
+impl<'a> Drop for Syscalls<'a> {
+    fn drop(&mut self) {
+        if let Err(e) = self.owner.stop_all() {
+            log::error!("failed to stop pid: {} with err: {:#?}", self.owner.pid, e);
+        }
+    }
+}
+
+impl<'a> Iterator for Syscalls<'a> {
+    type Item = Result<SyscallEvent, HostError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.step().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(sysno: Sysno, args: Vec<SyscallArg>, ret: Option<i64>) -> SyscallEvent {
+        SyscallEvent {
+            tid: Pid::from_raw(1),
+            sysno: Some(sysno),
+            nr: sysno.id() as u64,
+            raw_args: [0; 6],
+            args,
+            ret: ret.map(SyscallResult),
+        }
+    }
+
+    #[test]
+    fn display_write() {
+        let e = event(
+            Sysno::write,
+            vec![
+                SyscallArg::Fd(2),
+                SyscallArg::Buf(b"hi\n".to_vec(), 3),
+                SyscallArg::Int(3),
+            ],
+            Some(3),
+        );
+        assert_eq!(e.to_string(), r#"write(2, "hi\n", 3) = 3"#);
+    }
+
+    #[test]
+    fn display_errno() {
+        let e = event(
+            Sysno::openat,
+            vec![
+                SyscallArg::Fd(AT_FDCWD),
+                SyscallArg::Str(b"/nope".to_vec()),
+                SyscallArg::Hex(0),
+                SyscallArg::Hex(0),
+            ],
+            Some(-2),
+        );
+        assert_eq!(e.errno(), Some(Errno::ENOENT));
+        assert_eq!(
+            e.to_string(),
+            r#"openat(AT_FDCWD, "/nope", 0, 0) = -1 ENOENT (No such file or directory)"#
+        );
+    }
+
+    #[test]
+    fn no_return() {
+        let e = event(Sysno::exit_group, vec![SyscallArg::Int(0)], None);
+        assert_eq!(e.errno(), None);
+        assert_eq!(e.to_string(), "exit_group(0) = ?");
+    }
+
+    #[test]
+    fn unknown_syscall() {
+        let e = SyscallEvent {
+            sysno: Sysno::new(u64::MAX as usize),
+            nr: u64::MAX,
+            ..event(Sysno::getpid, vec![SyscallArg::Hex(1)], Some(-38))
+        };
+        assert_eq!(
+            e.to_string(),
+            "syscall_0xffffffffffffffff(0x1) = -1 ENOSYS (Function not implemented)"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{HostError, UProc};
+use nix::{libc::c_void, sys::ptrace, unistd::Pid};
+
+/// Offset of `u_debugreg` in the x86_64 `struct user`, also the layout seen by a 64-bit
+/// tracer of a 32-bit tracee.
+const DEBUGREG_OFFSET: usize = 848;
+
+/// Debug registers holding watched addresses, DR0 to DR3.
+pub const WATCHPOINT_SLOTS: usize = 4;
+
+/// DR6, which says which debug register fired.
+const DR6: usize = 6;
+
+/// DR7, which enables and configures the others.
+const DR7: usize = 7;
+
+/// Accesses that trigger a watchpoint. The x86 debug registers cannot watch reads only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    ReadWrite,
+}
+
+/// A hardware watchpoint, set in the same debug register of every thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// debug register number, 0 to 3
+    pub slot: usize,
+    pub addr: u64,
+    /// 1, 2, 4 or 8 bytes, `addr` is aligned to it
+    pub len: usize,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    /// Enable bit, access type and length of the slot in DR7.
+    fn dr7_bits(&self) -> u64 {
+        let rw = match self.kind {
+            WatchKind::Write => 0b01,
+            WatchKind::ReadWrite => 0b11,
+        };
+        let len = match self.len {
+            1 => 0b00,
+            2 => 0b01,
+            8 => 0b10,
+            _ => 0b11,
+        };
+        let shift = 16 + 4 * self.slot;
+        (1 << (2 * self.slot)) | (rw << shift) | (len << (shift + 2))
+    }
+}
+
+fn read_debugreg(tid: Pid, reg: usize) -> Result<u64, HostError> {
+    let offset = DEBUGREG_OFFSET + reg * 8;
+    Ok(ptrace::read_user(tid, offset as ptrace::AddressType)? as u64)
+}
+
+fn write_debugreg(tid: Pid, reg: usize, value: u64) -> Result<(), HostError> {
+    let offset = DEBUGREG_OFFSET + reg * 8;
+    // SAFETY: POKEUSER takes the value itself, not a pointer to it
+    unsafe { ptrace::write_user(tid, offset as ptrace::AddressType, value as *mut c_void)? };
+    Ok(())
+}
+
+impl UProc {
+    /// Watch `len` bytes at `addr` with a free debug register, in every thread and the ones
+    /// created later. The thread that accesses them stops right after the access with a
+    /// `StopReason::Watchpoint`, which `cont` reports as `Halt::Watchpoint`. Returns the
+    /// debug register used, to remove the watchpoint.
+    pub fn set_watchpoint(
+        &self,
+        addr: u64,
+        len: usize,
+        kind: WatchKind,
+    ) -> Result<usize, HostError> {
+        if !matches!(len, 1 | 2 | 4 | 8)
+            || !addr.is_multiple_of(len as u64)
+            || (len == 8 && self.arch().word_size() == 4)
+        {
+            return Err(HostError::BadWatchpoint { addr, len });
+        }
+        let slot = self
+            .watchpoints
+            .borrow()
+            .iter()
+            .position(Option::is_none)
+            .ok_or(HostError::NoFreeWatchpoint)?;
+
+        self.watchpoints.borrow_mut()[slot] = Some(Watchpoint {
+            slot,
+            addr,
+            len,
+            kind,
+        });
+        if let Err(e) = self.apply_watchpoints_all() {
+            self.watchpoints.borrow_mut()[slot] = None;
+            self.apply_watchpoints_all()?;
+            return Err(e);
+        }
+        log::debug!("pid: {} watchpoint {} set at {:#X}", self.pid, slot, addr);
+        Ok(slot)
+    }
+
+    pub fn remove_watchpoint(&self, slot: usize) -> Result<(), HostError> {
+        match self.watchpoints.borrow_mut().get_mut(slot) {
+            Some(watchpoint @ Some(_)) => *watchpoint = None,
+            _ => return Err(HostError::NoWatchpoint(slot)),
+        }
+        self.apply_watchpoints_all()?;
+        log::debug!("pid: {} watchpoint {} removed", self.pid, slot);
+        Ok(())
+    }
+
+    pub fn watchpoints(&self) -> Vec<Watchpoint> {
+        self.watchpoints
+            .borrow()
+            .iter()
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    pub(crate) fn remove_all_watchpoints(&self) -> Result<(), HostError> {
+        for watchpoint in self.watchpoints() {
+            self.remove_watchpoint(watchpoint.slot)?;
+        }
+        Ok(())
+    }
+
+    fn apply_watchpoints_all(&self) -> Result<(), HostError> {
+        for tid in self.threads() {
+            self.apply_watchpoints(tid)?;
+        }
+        Ok(())
+    }
+
+    /// Load the watchpoints into the debug registers of `tid`. Addresses go first, the kernel
+    /// checks them against the enabled lengths when DR7 is written.
+    pub(crate) fn apply_watchpoints(&self, tid: Pid) -> Result<(), HostError> {
+        let watchpoints = *self.watchpoints.borrow();
+        // nothing to clear in threads that never had any
+        if watchpoints.iter().all(Option::is_none) && read_debugreg(tid, DR7)? == 0 {
+            return Ok(());
+        }
+        write_debugreg(tid, DR7, 0)?;
+        let mut dr7 = 0;
+        for watchpoint in watchpoints.iter().flatten() {
+            write_debugreg(tid, watchpoint.slot, watchpoint.addr)?;
+            dr7 |= watchpoint.dr7_bits();
+        }
+        write_debugreg(tid, DR7, dr7)
+    }
+
+    /// The watchpoint that made `tid` trap, if any, after which DR6 is cleared: the CPU sets
+    /// its bits but never clears them.
+    pub(crate) fn take_watchpoint_hit(&self, tid: Pid) -> Result<Option<Watchpoint>, HostError> {
+        if self.watchpoints.borrow().iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        let dr6 = read_debugreg(tid, DR6)?;
+        let hit = self
+            .watchpoints
+            .borrow()
+            .iter()
+            .flatten()
+            .find(|watchpoint| dr6 & (1 << watchpoint.slot) != 0)
+            .copied();
+        if hit.is_some() {
+            write_debugreg(tid, DR6, 0)?;
+        }
+        Ok(hit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dr7_encoding() {
+        let watch = |slot, len, kind| {
+            Watchpoint {
+                slot,
+                addr: 0,
+                len,
+                kind,
+            }
+            .dr7_bits()
+        };
+        assert_eq!(watch(0, 1, WatchKind::Write), 0x0001_0001);
+        assert_eq!(watch(0, 4, WatchKind::ReadWrite), 0x000F_0001);
+        assert_eq!(watch(1, 8, WatchKind::Write), 0x0090_0004);
+        assert_eq!(watch(3, 2, WatchKind::ReadWrite), 0x7000_0040);
+    }
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use host::{SyscallArg, UProc};
+use nix::libc::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+use std::path::Path;
+use syscalls::Sysno;
+
+fn spawn_at_entry(path: &Path) -> UProc {
+    let proc = UProc::spawn(path, [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    common::run_to_entry(&proc);
+    proc
+}
+
+fn register(proc: &UProc, name: &str) -> u64 {
+    proc.registers().unwrap().get(name).unwrap()
+}
+
+#[test]
+fn x86_64_victim() {
+    let proc = spawn_at_entry(&common::victim());
+    assert_eq!(proc.arch().name(), "x86_64");
+    assert_eq!(proc.arch().word_size(), 8);
+    assert_eq!(register(&proc, "rip"), common::entry_point(proc.pid()));
+}
+
+#[test]
+#[ignore = "needs a 32-bit victim, see common::victim32"]
+fn i386_syscalls() {
+    let proc = spawn_at_entry(&common::victim32());
+    assert_eq!(proc.arch().name(), "i386");
+
+    let pid = proc.syscall(Sysno::getpid, 0, 0, 0, 0, 0, 0).unwrap();
+    assert_eq!(pid, proc.pid().as_raw() as u64);
+
+    let mem = proc
+        .malloc(
+            0,
+            4096,
+            (PROT_READ | PROT_WRITE) as u64,
+            (MAP_PRIVATE | MAP_ANONYMOUS) as u64,
+            u64::MAX,
+            0,
+        )
+        .unwrap();
+    assert!(mem.addr < 1 << 32);
+    mem.write_value(0, &0xdeadbeef_u32).unwrap();
+    assert_eq!(mem.read_value::<u32>(0).unwrap(), 0xdeadbeef);
+}
+
+#[test]
+#[ignore = "needs a 32-bit victim, see common::victim32"]
+fn i386_trace_syscalls() {
+    let proc = spawn_at_entry(&common::victim32());
+    let mut events = proc.syscalls().unwrap();
+    let write = events
+        .find_map(|event| {
+            let event = event.unwrap();
+            let logged =
+                event.sysno == Some(Sysno::write) && event.to_string().contains("Hello, world!");
+            logged.then_some(event)
+        })
+        .unwrap();
+    // the i386 number, with the arguments from ebx, ecx and edx
+    assert_eq!(write.nr, 4);
+    assert_eq!(write.args[0], SyscallArg::Fd(2));
+    assert!(write.ret.unwrap().value().unwrap() > 0);
+}
+
+#[test]
+#[ignore = "needs a 32-bit victim, see common::victim32"]
+fn i386_registers_and_breakpoints() {
+    let proc = spawn_at_entry(&common::victim32());
+    let regs = proc.registers().unwrap();
+    let names: Vec<&str> = regs.iter().map(|(n, _)| n).collect();
+    assert_eq!(
+        &names[..9],
+        ["eax", "ebx", "ecx", "edx", "esi", "edi", "ebp", "esp", "eip"]
+    );
+    assert_eq!(register(&proc, "eip"), common::entry_point(proc.pid()));
+
+    let main = proc.symbols().unwrap().resolve("main").unwrap();
+    proc.set_breakpoint(main).unwrap();
+    assert_eq!(proc.continue_until_breakpoint().unwrap(), main);
+    assert_eq!(register(&proc, "eip"), main);
+}
+
+#[test]
+#[ignore = "needs a 32-bit victim, see common::victim32"]
+fn i386_call_function() {
+    let proc = spawn_at_entry(&common::victim32());
+    let symbols = proc.symbols().unwrap();
+    let getpid = symbols.resolve("getpid").unwrap();
+    let abs = symbols.resolve("abs").unwrap();
+
+    let esp = register(&proc, "esp");
+    assert_eq!(
+        proc.call_function(getpid, &[]).unwrap(),
+        proc.pid().as_raw() as u64
+    );
+    // cdecl, the argument is passed on the stack
+    assert_eq!(
+        proc.call_function(abs, &[(-7i32) as u32 as u64]).unwrap(),
+        7
+    );
+    assert_eq!(register(&proc, "esp"), esp);
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::Victim;
+use host::{Frame, UProc, UnwindMethod};
+use nix::libc::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_EXEC, PROT_READ, PROT_WRITE};
+
+fn functions(frames: &[Frame]) -> Vec<&str> {
+    frames
+        .iter()
+        .map(|frame| frame.function.as_deref().unwrap_or("??"))
+        .collect()
+}
+
+#[test]
+fn unwind_through_libc() {
+    let victim = Victim::start(&[]);
+    let proc = UProc::seize(victim.pid).unwrap();
+
+    let frames = proc.backtrace(victim.pid).unwrap();
+    let names = functions(&frames);
+    assert_eq!(
+        frames[0].module.as_deref(),
+        Some("libc.so.6"),
+        "{:?}",
+        names
+    );
+    assert_eq!(frames[0].method, UnwindMethod::Registers);
+    assert!(names.contains(&"victim::main"), "{:?}", names);
+    assert!(names.contains(&"__libc_start_main"), "{:?}", names);
+    assert_eq!(names.last(), Some(&"_start"), "{:?}", names);
+
+    // libc and the victim have no frame pointers, every caller comes from the CFI
+    assert!(frames[1..].iter().all(|f| f.method == UnwindMethod::Cfi));
+    assert!(frames.windows(2).all(|w| w[0].sp < w[1].sp));
+}
+
+#[test]
+fn unwind_frame_pointers_without_cfi() {
+    let proc = common::spawn_victim(&[]);
+    let saved = proc.registers().unwrap();
+    // code mapped by hand has no .eh_frame, the unwinder falls back to frame pointers
+    let mem = proc
+        .malloc(
+            0,
+            4096,
+            (PROT_READ | PROT_WRITE | PROT_EXEC) as u64,
+            (MAP_PRIVATE | MAP_ANONYMOUS) as u64,
+            u64::MAX,
+            0,
+        )
+        .unwrap();
+    let code = mem.addr;
+    // endbr64; push rbp; mov rbp, rsp; nop; pop rbp; ret
+    let func = [
+        0xF3, 0x0F, 0x1E, 0xFA, 0x55, 0x48, 0x89, 0xE5, 0x90, 0x5D, 0xC3,
+    ];
+    let (entry, setup, body, ret) = (code, code + 5, code + 8, code + 10);
+    mem.write_bytes(0, &func).unwrap();
+
+    // a stack of two frames: the callee returns into the body of the caller, whose caller
+    // returns to the `ret` and ends the frame pointer chain
+    let stack = code + 0x800;
+    let caller_fp = stack + 0x30;
+    for (addr, value) in [
+        (stack, caller_fp),
+        (stack + 0x08, body + 1),
+        (caller_fp, 0),
+        (caller_fp + 8, ret),
+    ] {
+        proc.write_value(addr, &value).unwrap();
+    }
+
+    // in the body, before the prologue, after it pushed the frame pointer, and at the `ret`
+    for (ip, sp, fp) in [
+        (body, stack - 0x10, stack),
+        (entry, stack + 0x08, caller_fp),
+        (setup, stack, caller_fp),
+        (ret, stack + 0x08, caller_fp),
+    ] {
+        let mut regs = saved;
+        regs.set("rip", ip).unwrap();
+        regs.set("rsp", sp).unwrap();
+        regs.set("rbp", fp).unwrap();
+        proc.set_registers(&regs).unwrap();
+
+        let frames = proc.backtrace(proc.pid()).unwrap();
+        let ips: Vec<u64> = frames.iter().map(|frame| frame.ip).collect();
+        assert_eq!(ips, [ip, body + 1, ret], "at {:#x}", ip);
+        let methods: Vec<UnwindMethod> = frames.iter().map(|frame| frame.method).collect();
+        assert_eq!(
+            methods,
+            [
+                UnwindMethod::Registers,
+                UnwindMethod::FramePointer,
+                UnwindMethod::FramePointer
+            ]
+        );
+        assert_eq!(frames[1].sp, stack + 0x10);
+        assert!(frames.iter().all(|frame| frame.module.is_none()));
+    }
+    proc.set_registers(&saved).unwrap();
+}
+
+#[test]
+fn unwind_worker_thread() {
+    let victim = Victim::start(&["--threads", "1"]);
+    let proc = UProc::seize(victim.pid).unwrap();
+    let worker = proc.threads()[1];
+    let symbols = proc.symbols().unwrap();
+
+    let frames = proc.backtrace_with(worker, &symbols).unwrap();
+    let names = functions(&frames);
+    assert!(names.contains(&"victim_worker"), "{:?}", names);
+    assert!(!names.contains(&"victim::main"), "{:?}", names);
+    // start_thread and clone3 are not exported by libc
+    let last = frames.last().unwrap();
+    assert_eq!(last.module.as_deref(), Some("libc.so.6"), "{:?}", names);
+}
+
+#[test]
+fn frame_display() {
+    let victim = Victim::start(&[]);
+    let proc = UProc::seize(victim.pid).unwrap();
+    let tick = victim.addr("victim_tick");
+    proc.set_breakpoint(tick).unwrap();
+    proc.continue_until_breakpoint().unwrap();
+
+    let frames = proc.backtrace(victim.pid).unwrap();
+    assert_eq!(
+        frames[0].to_string(),
+        format!("{:#x} in victim!victim_tick+0x0", tick)
+    );
+    assert_eq!(frames[1].function.as_deref(), Some("victim::main"));
+    assert!(frames[1]
+        .to_string()
+        .starts_with(&format!("{:#x} in victim!victim::main+0x", frames[1].ip)));
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use host::{Halt, HostError, UProc};
+use nix::sys::ptrace;
+
+#[test]
+fn break_at_entry() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let entry = common::entry_point(proc.pid());
+    let orig = proc.mem_read(entry, 1).unwrap();
+
+    proc.set_breakpoint(entry).unwrap();
+    assert_eq!(proc.mem_read(entry, 1).unwrap(), [0xCC]);
+    assert_eq!(proc.breakpoints(), [entry]);
+
+    assert_eq!(proc.continue_until_breakpoint().unwrap(), entry);
+    assert_eq!(ptrace::getregs(proc.pid()).unwrap().rip, entry);
+
+    proc.remove_breakpoint(entry).unwrap();
+    assert_eq!(proc.mem_read(entry, 1).unwrap(), orig);
+    assert!(proc.breakpoints().is_empty());
+}
+
+#[test]
+fn remove_unknown_breakpoint() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    assert!(matches!(
+        proc.remove_breakpoint(0x1000),
+        Err(HostError::NoBreakpoint(0x1000))
+    ));
+}
+
+#[test]
+fn step_out_of_function() {
+    let proc = common::spawn_victim(&["--interval", "10", "--quiet"]);
+    let symbols = proc.symbols().unwrap();
+    let tick = symbols.resolve("victim_tick").unwrap();
+    proc.set_breakpoint(tick).unwrap();
+    proc.continue_until_breakpoint().unwrap();
+
+    let frames = proc.backtrace(proc.pid()).unwrap();
+    assert_eq!(frames[0].ip, tick);
+    let caller = symbols.symbolize(frames[1].ip).unwrap();
+    assert_eq!(caller.symbol.unwrap().name, "victim::main");
+
+    // stepping over the breakpoint and the rest of the function ends up in the caller
+    let mut steps = 0;
+    loop {
+        match proc.step_over().unwrap() {
+            Halt::Stepped { addr } if addr == frames[1].ip => break,
+            Halt::Stepped { .. } => steps += 1,
+            halt => panic!("{:?}", halt),
+        }
+        assert!(steps < 1000);
+    }
+    assert_eq!(proc.registers().unwrap().sp(), frames[1].sp);
+    assert_eq!(proc.mem_read(tick, 1).unwrap(), [0xCC]);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::Victim;
+use host::{HostError, UProc};
+use nix::{
+    libc::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE},
+    sys::{ptrace, signal::Signal},
+};
+use syscalls::Sysno;
+
+#[test]
+fn call_getpid_restores_registers() {
+    let proc = common::spawn_at_entry();
+    let getpid = proc.symbols().unwrap().resolve("getpid").unwrap();
+
+    let before = ptrace::getregs(proc.pid()).unwrap();
+    let code = proc.mem_read(before.rip, 16).unwrap();
+    let pid = proc.call_function(getpid, &[]).unwrap();
+    let after = ptrace::getregs(proc.pid()).unwrap();
+
+    assert_eq!(pid, proc.pid().as_raw() as u64);
+    assert_eq!(format!("{:?}", before), format!("{:?}", after));
+    assert_eq!(proc.mem_read(before.rip, 16).unwrap(), code);
+}
+
+#[test]
+fn call_with_stack_arguments() {
+    let proc = common::spawn_at_entry();
+    let symbols = proc.symbols().unwrap();
+    let snprintf = symbols.resolve("snprintf").unwrap();
+    let atoi = symbols.resolve("atoi").unwrap();
+
+    let mem = proc
+        .malloc(
+            0,
+            4096,
+            (PROT_READ | PROT_WRITE) as u64,
+            (MAP_PRIVATE | MAP_ANONYMOUS) as u64,
+            u64::MAX,
+            0,
+        )
+        .unwrap();
+    let fmt = mem.addr;
+    let buf = mem.addr + 256;
+    proc.mem_write(fmt, b"%d %d %d %d %d\0").unwrap();
+
+    // eight arguments, the last two on the stack
+    let written = proc
+        .call_function(snprintf, &[buf, 64, fmt, 1, 2, 3, 4, 5])
+        .unwrap();
+    assert_eq!(written, 9);
+    assert_eq!(proc.mem_read_cstr(buf, 64).unwrap(), b"1 2 3 4 5");
+    assert_eq!(proc.call_function(atoi, &[buf + 8]).unwrap(), 5);
+}
+
+#[test]
+fn faulting_call_restores_registers() {
+    let proc = common::spawn_at_entry();
+
+    let before = ptrace::getregs(proc.pid()).unwrap();
+    let code = proc.mem_read(before.rip, 16).unwrap();
+    match proc.call_function(0x10, &[]) {
+        Err(HostError::CallFaulted {
+            addr: 0x10,
+            signal: Signal::SIGSEGV,
+        }) => {}
+        res => panic!("unexpected {:?}", res),
+    }
+    let after = ptrace::getregs(proc.pid()).unwrap();
+
+    assert_eq!(format!("{:?}", before), format!("{:?}", after));
+    assert_eq!(proc.mem_read(before.rip, 16).unwrap(), code);
+    // the fault is not delivered, the process carries on
+    let pid = proc.syscall(Sysno::getpid, 0, 0, 0, 0, 0, 0).unwrap();
+    assert_eq!(pid, proc.pid().as_raw() as u64);
+}
+
+#[test]
+fn call_exported_functions() {
+    let victim = Victim::start(&[]);
+    let proc = UProc::seize(victim.pid).unwrap();
+    let symbols = proc.symbols().unwrap();
+
+    let add = symbols.resolve("victim_add").unwrap();
+    assert_eq!(add, victim.addr("victim_add"));
+    assert_eq!(proc.call_function(add, &[2, 3]).unwrap(), 5);
+
+    let sum8 = symbols.resolve("victim_sum8").unwrap();
+    let sum = proc.call_function(sum8, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+    assert_eq!(sum, 36);
+    // wraps like the C it stands in for
+    let sum = proc
+        .call_function(sum8, &[u64::MAX, 2, 0, 0, 0, 0, 0, 0])
+        .unwrap();
+    assert_eq!(sum, 1);
+}
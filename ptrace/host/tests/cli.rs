@@ -0,0 +1,135 @@
+mod common;
+
+use common::Victim;
+use serde_json::Value;
+use std::process::{Command, Output};
+
+fn host(args: &[&str]) -> Output {
+    let out = Command::new(env!("CARGO_BIN_EXE_host"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "host {:?}: {}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    out
+}
+
+fn json(args: &[&str]) -> Value {
+    let mut args = args.to_vec();
+    args.push("--json");
+    serde_json::from_slice(&host(&args).stdout).unwrap()
+}
+
+#[test]
+fn peek_and_poke() {
+    let victim = Victim::start(&[]);
+    let pid = victim.pid.to_string();
+
+    let text = host(&["peek", "--pid", &pid, "VICTIM_MAGIC", "8"]).stdout;
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains("0d f0 fe ca ef be ad de"), "{}", text);
+
+    let counter = format!("{:#x}", victim.addr("counter"));
+    host(&["poke", "--pid", &pid, &counter, "2a 00 00", "00"]);
+    let peek = json(&["peek", "--pid", &pid, &counter, "8"]);
+    assert_eq!(peek["hex"], "2a00000000000000");
+    assert_eq!(peek["addr"], victim.addr("counter"));
+}
+
+#[test]
+fn syscall_regs_maps() {
+    let victim = Victim::start(&[]);
+    let pid = victim.pid.to_string();
+
+    let getpid = json(&["syscall", "--pid", &pid, "getpid"]);
+    assert_eq!(getpid["ret"], victim.pid.as_raw());
+    let close = json(&["syscall", "--pid", &pid, "close", "-1"]);
+    assert_eq!(close["errno"], "EBADF");
+
+    let regs = json(&["regs", "--pid", &pid]);
+    assert!(regs["rip"].as_u64().unwrap() > 0);
+
+    let maps = json(&["maps", "--pid", &pid]);
+    let victim_path = common::victim();
+    assert!(maps
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|r| r["path"] == victim_path.to_str().unwrap()));
+
+    let attach = json(&["attach", "--pid", &pid]);
+    assert_eq!(attach["threads"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn ps_lists_victim() {
+    let victim = Victim::start(&[]);
+    let ps = json(&["ps", "victim"]);
+    assert!(ps
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p["pid"] == victim.pid.as_raw()));
+}
+
+#[test]
+fn profile_folded() {
+    let victim = Victim::start(&["--block", "spin"]);
+    let pid = victim.pid.to_string();
+
+    let out = host(&[
+        "profile",
+        "--pid",
+        &pid,
+        "--duration",
+        "0.2",
+        "--frequency",
+        "100",
+    ]);
+    let folded = String::from_utf8(out.stdout).unwrap();
+    let total: u64 = folded
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+        .sum();
+    assert!(total > 0);
+    assert!(
+        folded.contains(";victim::main;victim::spin_for"),
+        "{}",
+        folded
+    );
+    assert!(String::from_utf8_lossy(&out.stderr).contains(&format!("{} samples", total)));
+
+    let profile = json(&["profile", "--pid", &pid, "--duration", "0.1"]);
+    assert!(profile["samples"].as_u64().unwrap() > 0);
+    assert_eq!(profile["stacks"][0]["frames"][0], "victim");
+}
+
+#[test]
+fn fds_and_redirect() {
+    let victim = Victim::start(&[]);
+    let pid = victim.pid.to_string();
+    let log = std::env::temp_dir().join(format!("host-cli-redirect-{}", victim.pid));
+
+    let fds = json(&["fds", "--pid", &pid]);
+    assert_eq!(fds[0]["fd"], 0);
+    assert_eq!(fds[1]["access"], "w");
+
+    let redirect = json(&[
+        "redirect",
+        "--pid",
+        &pid,
+        "--fd",
+        "2",
+        log.to_str().unwrap(),
+    ]);
+    assert_eq!(redirect["fd"], 2);
+    assert!(redirect["saved"].as_i64().unwrap() > 2);
+
+    let fds = json(&["fds", "--pid", &pid]);
+    assert_eq!(fds[2]["path"], log.to_str().unwrap());
+    std::fs::remove_file(&log).unwrap();
+}
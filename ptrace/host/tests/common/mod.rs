@@ -0,0 +1,142 @@
+#![allow(dead_code)]
+
+use nix::{
+    libc::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE},
+    unistd::Pid,
+};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+};
+
+/// Path of the `victim` binary built alongside the integration tests.
+pub fn victim() -> PathBuf {
+    let mut dir = std::env::current_exe().expect("test executable path");
+    dir.pop();
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+    let path = dir.join("victim");
+    assert!(
+        path.exists(),
+        "{} missing, run `cargo build -p victim` first",
+        path.display()
+    );
+    path
+}
+
+/// 32-bit build of `victim`, from `VICTIM32` or the i686 target directory. Needs a multilib
+/// toolchain: `cargo build -p victim --target i686-unknown-linux-gnu`.
+pub fn victim32() -> PathBuf {
+    if let Some(path) = std::env::var_os("VICTIM32") {
+        return path.into();
+    }
+    let debug = victim();
+    let target = debug.parent().unwrap().parent().unwrap();
+    let path = target.join("i686-unknown-linux-gnu/debug/victim");
+    assert!(
+        path.exists(),
+        "{} missing, run `cargo build -p victim --target i686-unknown-linux-gnu` first",
+        path.display()
+    );
+    path
+}
+
+/// `AT_ENTRY` of a process, read from its auxiliary vector.
+pub fn entry_point(pid: nix::unistd::Pid) -> u64 {
+    // ELF class of the executable, the auxv holds native words
+    let exe = std::fs::read(format!("/proc/{}/exe", pid)).unwrap();
+    let word = if exe[4] == 1 { 4 } else { 8 };
+    let auxv = std::fs::read(format!("/proc/{}/auxv", pid)).unwrap();
+    let int = |b: &[u8]| {
+        let mut buf = [0u8; 8];
+        buf[..b.len()].copy_from_slice(b);
+        u64::from_le_bytes(buf)
+    };
+    auxv.chunks_exact(word * 2)
+        .map(|kv| {
+            let (k, v) = kv.split_at(word);
+            (int(k), int(v))
+        })
+        .find(|(k, _)| *k == nix::libc::AT_ENTRY)
+        .map(|(_, v)| v)
+        .unwrap()
+}
+
+/// Run a freshly spawned process up to its entry point, once the dynamic loader mapped libc.
+pub fn run_to_entry(proc: &host::UProc) {
+    let entry = entry_point(proc.pid());
+    proc.set_breakpoint(entry).unwrap();
+    proc.continue_until_breakpoint().unwrap();
+    proc.remove_breakpoint(entry).unwrap();
+}
+
+/// Spawn `victim` with `args` as a tracee, stopped at its exec.
+pub fn spawn_victim(args: &[&str]) -> host::UProc {
+    host::UProc::spawn(victim(), args, [] as [(&str, &str); 0]).unwrap()
+}
+
+/// Spawn `victim` without arguments and run it up to its entry point.
+pub fn spawn_at_entry() -> host::UProc {
+    let proc = spawn_victim(&[]);
+    run_to_entry(&proc);
+    proc
+}
+
+/// Map `len` bytes of private anonymous read-write memory in the tracee.
+pub fn alloc(proc: &host::UProc, len: u64) -> host::UProcMem<'_> {
+    proc.malloc(
+        0,
+        len,
+        (PROT_READ | PROT_WRITE) as u64,
+        (MAP_PRIVATE | MAP_ANONYMOUS) as u64,
+        u64::MAX,
+        0,
+    )
+    .unwrap()
+}
+
+/// `victim` running untraced, like a process attached to later, killed when dropped.
+pub struct Victim {
+    child: Child,
+    pub pid: Pid,
+    addrs: HashMap<String, u64>,
+}
+
+impl Victim {
+    /// Start `victim` with `args` and wait until it printed the addresses of its variables.
+    pub fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(victim())
+            .args(args)
+            .args(["--print-addrs", "--quiet"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut addrs = HashMap::new();
+        for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+            let line = line.unwrap();
+            if line == "ready" {
+                break;
+            }
+            let (name, addr) = line.split_once(' ').unwrap();
+            let addr = u64::from_str_radix(addr.trim_start_matches("0x"), 16).unwrap();
+            addrs.insert(name.to_string(), addr);
+        }
+        let pid = Pid::from_raw(child.id() as i32);
+        Self { child, pid, addrs }
+    }
+
+    /// Address printed by `--print-addrs`, like `heap_value` or `victim_add`.
+    pub fn addr(&self, name: &str) -> u64 {
+        self.addrs[name]
+    }
+}
+
+impl Drop for Victim {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
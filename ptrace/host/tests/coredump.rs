@@ -0,0 +1,98 @@
+mod common;
+
+use host::UProc;
+use object::{
+    elf,
+    read::elf::{ElfFile64, FileHeader, ProgramHeader},
+    Endianness,
+};
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[test]
+fn dump_victim() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    common::run_to_entry(&proc);
+    let regs = proc.registers().unwrap();
+    let main = proc.symbols().unwrap().resolve("main").unwrap();
+    let main_byte = proc.mem_read(main, 1).unwrap()[0];
+    proc.set_breakpoint(main).unwrap();
+
+    let path = std::env::temp_dir().join(format!("victim-{}.core", proc.pid()));
+    proc.dump_core(&path).unwrap();
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let core = ElfFile64::<Endianness>::parse(&*data).unwrap();
+    let header = core.elf_header();
+    let endian = header.endian().unwrap();
+    assert_eq!(header.e_type(endian), elf::ET_CORE);
+
+    let mut notes = Vec::new();
+    let mut loads = Vec::new();
+    for phdr in core.elf_program_headers() {
+        match phdr.p_type(endian) {
+            elf::PT_NOTE => {
+                let mut iter = phdr.notes(endian, &*data).unwrap().unwrap();
+                while let Some(note) = iter.next().unwrap() {
+                    assert_eq!(note.name(), b"CORE");
+                    notes.push((note.n_type(endian), note.desc().to_vec()));
+                }
+            }
+            elf::PT_LOAD => loads.push(*phdr),
+            _ => {}
+        }
+    }
+
+    let prstatus: Vec<&Vec<u8>> = notes
+        .iter()
+        .filter(|(kind, _)| *kind == elf::NT_PRSTATUS)
+        .map(|(_, desc)| desc)
+        .collect();
+    assert_eq!(prstatus.len(), proc.threads().len());
+    let status = prstatus[0];
+    assert_eq!(status.len(), 336);
+    assert_eq!(
+        u32::from_le_bytes(status[32..36].try_into().unwrap()),
+        proc.pid().as_raw() as u32
+    );
+    // pr_reg starts at 112, rip is the 17th register
+    assert_eq!(u64_at(status, 112 + 16 * 8), regs.ip());
+    assert_eq!(u64_at(status, 112 + 19 * 8), regs.sp());
+
+    let auxv = notes
+        .iter()
+        .find(|(kind, _)| *kind == elf::NT_AUXV)
+        .unwrap();
+    assert_eq!(
+        auxv.1,
+        std::fs::read(format!("/proc/{}/auxv", proc.pid())).unwrap()
+    );
+
+    let files = &notes
+        .iter()
+        .find(|(kind, _)| *kind == elf::NT_FILE)
+        .unwrap()
+        .1;
+    let victim = common::victim();
+    let victim = victim.to_str().unwrap().as_bytes();
+    assert!(u64_at(files, 0) > 0);
+    assert!(files.windows(victim.len()).any(|w| w == victim));
+
+    let core_offset = |addr: u64| {
+        let load = loads
+            .iter()
+            .find(|p| p.p_vaddr(endian) <= addr && addr < p.p_vaddr(endian) + p.p_filesz(endian))
+            .unwrap();
+        (load.p_offset(endian) + addr - load.p_vaddr(endian)) as usize
+    };
+    // the code at rip is in a loaded segment with the same bytes as the live process
+    let rip = regs.ip();
+    let offset = core_offset(rip);
+    assert_eq!(&data[offset..offset + 16], proc.mem_read(rip, 16).unwrap());
+    // without the int3 of the breakpoint
+    assert_eq!(proc.mem_read(main, 1).unwrap(), [0xCC]);
+    assert_eq!(data[core_offset(main)], main_byte);
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::Victim;
+use host::UProc;
+use nix::libc::{AF_UNIX, O_APPEND, SOCK_STREAM};
+use std::{fs, io::Write, os::unix::fs::MetadataExt, path::PathBuf, time::Duration};
+use syscalls::Sysno;
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("host-{}-{}", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn list_fds() {
+    let victim = Victim::start(&[]);
+    let proc = UProc::attach(victim.pid).unwrap();
+    let fds = proc.fds().unwrap();
+    let numbers: Vec<i32> = fds.iter().map(|info| info.fd).collect();
+    assert_eq!(numbers[..3], [0, 1, 2]);
+
+    // stdout is the pipe the test reads the addresses from
+    let stdout = &fds[1];
+    assert!(
+        stdout.path.to_string_lossy().starts_with("pipe:["),
+        "{:?}",
+        stdout
+    );
+    assert_eq!(stdout.access(), "w");
+
+    let sock = proc
+        .syscall(
+            Sysno::socket,
+            AF_UNIX as u64,
+            SOCK_STREAM as u64,
+            0,
+            0,
+            0,
+            0,
+        )
+        .unwrap() as i32;
+    let info = proc
+        .fds()
+        .unwrap()
+        .into_iter()
+        .find(|info| info.fd == sock)
+        .unwrap();
+    let inode = fs::metadata(format!("/proc/{}/fd/{}", victim.pid, sock))
+        .unwrap()
+        .ino();
+    assert_eq!(info.socket_inode, Some(inode));
+    assert_eq!(info.access(), "rw");
+}
+
+#[test]
+fn redirect_and_dup_stderr() {
+    let proc = common::spawn_victim(&["--interval", "10"]);
+    common::run_to_entry(&proc);
+    let stderr = proc.fds().unwrap()[2].clone();
+    let log = temp_path("redirect");
+
+    let saved = proc.redirect_fd(2, &log).unwrap();
+    let fds = proc.fds().unwrap();
+    assert_eq!(fds[2].path, log);
+    assert_ne!(fds[2].flags & O_APPEND, 0);
+    assert_eq!(
+        fds.iter().find(|info| info.fd == saved).unwrap().path,
+        stderr.path
+    );
+
+    proc.resume().unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    proc.interrupt().unwrap();
+    let logged = fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("Hello, world!"), "{}", logged);
+    assert!(proc.fds().unwrap()[2].pos > 0);
+
+    // the host writes to the same open file
+    let mut file = fs::File::from(proc.dup_fd(2).unwrap());
+    file.write_all(b"from the host\n").unwrap();
+    assert!(fs::read_to_string(&log)
+        .unwrap()
+        .ends_with("from the host\n"));
+
+    proc.restore_fd(2, saved).unwrap();
+    let fds = proc.fds().unwrap();
+    assert_eq!(fds[2].path, stderr.path);
+    assert!(fds.iter().all(|info| info.fd != saved));
+    fs::remove_file(&log).unwrap();
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use host::{Halt, HostError, Stop, StopReason, UProc};
+use std::path::Path;
+
+/// Resume every thread until one stops with something `f` accepts, letting the rest go.
+fn wait_for<T>(proc: &UProc, f: impl Fn(Stop) -> Option<T>) -> T {
+    proc.resume_all().unwrap();
+    loop {
+        let stop = proc.wait_any().unwrap();
+        if let Some(found) = f(stop) {
+            return found;
+        }
+        proc.resume_all().unwrap();
+    }
+}
+
+#[test]
+fn follow_fork() {
+    let proc = common::spawn_victim(&["--fork", "--interval", "10", "--quiet"]);
+    common::run_to_entry(&proc);
+    let tick = proc.symbols().unwrap().resolve("victim_tick").unwrap();
+    proc.set_breakpoint(tick).unwrap();
+
+    let (tid, child) = wait_for(&proc, |stop| match stop.reason {
+        StopReason::Fork { child, vfork } => {
+            assert!(!vfork);
+            Some((stop.tid, child))
+        }
+        _ => None,
+    });
+    assert_eq!(tid, proc.pid());
+    let child = proc.take_child(child).unwrap();
+    assert!(proc.take_child(child.pid()).is_none());
+    assert_eq!(child.threads(), [child.pid()]);
+
+    // the child has its own copy of the breakpoint
+    assert_eq!(child.breakpoints(), [tick]);
+    assert_eq!(child.continue_until_breakpoint().unwrap(), tick);
+    assert_eq!(child.current_thread(), child.pid());
+    child.remove_breakpoint(tick).unwrap();
+    assert_ne!(child.mem_read(tick, 1).unwrap(), [0xCC]);
+    assert_eq!(proc.mem_read(tick, 1).unwrap(), [0xCC]);
+
+    // the parent still hits its own
+    assert_eq!(proc.continue_until_breakpoint().unwrap(), tick);
+}
+
+#[test]
+fn vfork_then_exec() {
+    let true_path = Path::new("/bin/true").canonicalize().unwrap();
+    let proc = common::spawn_victim(&["--spawn", "/bin/true", "--interval", "10", "--quiet"]);
+
+    let child = match proc.cont().unwrap() {
+        Halt::Fork { tid, child } => {
+            assert_eq!(tid, proc.pid());
+            proc.take_child(child).unwrap()
+        }
+        halt => panic!("{:?}", halt),
+    };
+    assert_eq!(child.exe().unwrap(), common::victim());
+
+    // the parent waits for the child to exec
+    assert_eq!(child.cont().unwrap(), Halt::Exec);
+    assert_eq!(child.exe().unwrap(), true_path);
+    assert!(matches!(
+        child.cont(),
+        Err(HostError::ProcessExited(Stop {
+            reason: StopReason::Exited(0),
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn exec_clears_breakpoints() {
+    let proc = common::spawn_victim(&[
+        "--ticks",
+        "1",
+        "--interval",
+        "1",
+        "--exec",
+        "/bin/true",
+        "--quiet",
+    ]);
+    common::run_to_entry(&proc);
+    let tick = proc.symbols().unwrap().resolve("victim_tick").unwrap();
+    proc.set_breakpoint(tick).unwrap();
+    assert_eq!(
+        proc.cont().unwrap(),
+        Halt::Breakpoint {
+            tid: proc.pid(),
+            addr: tick
+        }
+    );
+
+    assert_eq!(proc.cont().unwrap(), Halt::Exec);
+    assert_eq!(
+        proc.exe().unwrap(),
+        Path::new("/bin/true").canonicalize().unwrap()
+    );
+    assert!(proc.breakpoints().is_empty());
+    assert_eq!(proc.threads(), [proc.pid()]);
+    assert!(proc.symbols().unwrap().resolve("victim_tick").is_none());
+}
+
+#[test]
+fn exit_stop() {
+    let proc = common::spawn_victim(&["--ticks", "2", "--interval", "1", "--quiet"]);
+    let counter = proc.symbols().unwrap().resolve("VICTIM_COUNTER").unwrap();
+
+    let (tid, status) = wait_for(&proc, |stop| match stop.reason {
+        StopReason::Exiting(status) => Some((stop.tid, status)),
+        _ => None,
+    });
+    assert_eq!(tid, proc.pid());
+    assert_eq!(status, 0);
+    // still there to be inspected
+    assert_eq!(proc.read_value::<u64>(counter).unwrap(), 2);
+
+    proc.resume_all().unwrap();
+    assert_eq!(proc.wait_any().unwrap().reason, StopReason::Exited(0));
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use host::{HostError, UProc};
+
+#[test]
+fn inject_system_library() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    common::run_to_entry(&proc);
+    let loaded = |name: &str| {
+        proc.maps()
+            .unwrap()
+            .iter()
+            .any(|r| r.path.as_deref().is_some_and(|p| p.ends_with(name)))
+    };
+    assert!(!loaded("/libm.so.6"));
+
+    let handle = proc.inject_library("libm.so.6").unwrap();
+    assert_ne!(handle, 0);
+    assert!(loaded("/libm.so.6"));
+    assert!(proc.symbols().unwrap().resolve("cbrt").is_some());
+
+    // dlopen of a loaded library returns the same handle
+    assert_eq!(proc.inject_library("libm.so.6").unwrap(), handle);
+}
+
+#[test]
+fn inject_missing_library() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    common::run_to_entry(&proc);
+
+    match proc.inject_library("/nonexistent/libpayload.so") {
+        Err(HostError::DlOpen(msg)) => assert!(msg.contains("No such file"), "{}", msg),
+        res => panic!("unexpected {:?}", res),
+    }
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use host::{HostError, UProc};
+
+#[test]
+fn victim_maps() {
+    let victim = common::victim();
+    let proc = UProc::spawn(&victim, [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let maps = proc.maps().unwrap();
+
+    let exe = victim.to_str().unwrap();
+    assert!(maps
+        .iter()
+        .any(|r| r.path.as_deref() == Some(exe) && r.perms.exec));
+    assert!(maps.iter().any(|r| r.path.as_deref() == Some("[stack]")));
+    assert!(maps.windows(2).all(|w| w[0].end <= w[1].start));
+
+    let entry = common::entry_point(proc.pid());
+    assert_eq!(
+        proc.mem_read_mapped(entry, 16).unwrap(),
+        proc.mem_read(entry, 16).unwrap()
+    );
+}
+
+#[test]
+fn crossing_unmapped_memory() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let maps = proc.maps().unwrap();
+    let gap = maps
+        .windows(2)
+        .find(|w| w[0].end < w[1].start)
+        .map(|w| w[0].end)
+        .unwrap();
+
+    assert!(matches!(
+        proc.mem_read_mapped(gap - 4, 8),
+        Err(HostError::UnmappedMemory { addr, len: 8 }) if addr == gap
+    ));
+    assert!(matches!(
+        proc.mem_write_mapped(gap, &[0; 4]),
+        Err(HostError::UnmappedMemory { addr, len: 4 }) if addr == gap
+    ));
+    // the unchecked read no longer returns a silently truncated buffer
+    assert!(proc.mem_read(gap - 4, 8).is_err());
+}
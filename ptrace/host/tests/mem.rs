@@ -0,0 +1,54 @@
+mod common;
+
+use host::{HostError, UProc};
+
+fn mapped(proc: &UProc, addr: u64) -> bool {
+    proc.maps().unwrap().iter().any(|r| r.contains(addr))
+}
+
+#[test]
+fn typed_access() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let mem = common::alloc(&proc, 64);
+
+    mem.write_value(8, &-42i64).unwrap();
+    mem.write_value(16, &[1u32, 2, 3]).unwrap();
+    assert_eq!(mem.read_value::<i64>(8).unwrap(), -42);
+    assert_eq!(mem.read_value::<[u32; 3]>(16).unwrap(), [1, 2, 3]);
+    assert_eq!(proc.read_value::<i64>(mem.addr + 8).unwrap(), -42);
+
+    let all = mem.to_vec().unwrap();
+    assert_eq!(all.len(), 64);
+    assert_eq!(&all[8..16], (-42i64).to_le_bytes());
+
+    mem.write_bytes(60, b"tail").unwrap();
+    assert_eq!(mem.read_bytes(60, 4).unwrap(), b"tail");
+}
+
+#[test]
+fn out_of_bounds() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let mem = common::alloc(&proc, 16);
+
+    assert!(matches!(
+        mem.read_value::<u64>(12),
+        Err(HostError::OutOfBounds {
+            offset: 12,
+            len: 8,
+            size: 16
+        })
+    ));
+    assert!(mem.write_bytes(u64::MAX, b"x").is_err());
+    assert!(mem.read_bytes(0, 17).is_err());
+}
+
+#[test]
+fn unmapped_on_drop() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+
+    let addr = common::alloc(&proc, 4096).addr;
+    assert!(!mapped(&proc, addr));
+
+    let leaked = common::alloc(&proc, 4096).leak();
+    assert!(mapped(&proc, leaked));
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::Victim;
+use host::UProc;
+use std::time::Duration;
+
+#[test]
+fn profile_spinning_threads() {
+    let victim = Victim::start(&["--block", "spin", "--threads", "1"]);
+    let proc = UProc::seize(victim.pid).unwrap();
+
+    let profile = proc.profile(200, Duration::from_millis(300)).unwrap();
+    // 60 samples of 2 threads, some may be late on a busy machine
+    assert!(profile.samples() >= 40, "{}", profile.samples());
+    assert_eq!(
+        profile.stacks().map(|(_, count)| count).sum::<u64>(),
+        profile.samples()
+    );
+
+    let spinning: u64 = profile
+        .stacks()
+        .filter(|(stack, _)| stack.iter().any(|f| f == "victim::spin_for"))
+        .map(|(_, count)| count)
+        .sum();
+    assert!(spinning * 10 >= profile.samples() * 9, "{}", profile);
+    // rooted at the thread name
+    assert!(profile.stacks().all(|(stack, _)| stack[0] == "victim"));
+    let workers = profile
+        .stacks()
+        .filter(|(stack, _)| stack.iter().any(|f| f == "victim_worker"))
+        .count();
+    assert!(workers > 0, "{}", profile);
+}
+
+#[test]
+fn profile_until_exit() {
+    let proc = common::spawn_victim(&["--ticks", "3", "--interval", "20", "--quiet"]);
+    let profile = proc.profile(100, Duration::from_secs(10)).unwrap();
+    assert!(profile.samples() > 0);
+    assert!(profile.samples() < 100, "{}", profile.samples());
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use host::{Eflags, HostError};
+use syscalls::Sysno;
+
+#[test]
+fn set_by_name() {
+    let proc = common::spawn_at_entry();
+    let mut regs = proc.registers().unwrap();
+    assert_eq!(regs.ip(), common::entry_point(proc.pid()));
+    assert!(regs.eflags().contains(Eflags::IF));
+
+    regs.set("r12", 0x1234).unwrap();
+    proc.set_registers(&regs).unwrap();
+    assert_eq!(proc.registers().unwrap().get("r12"), Some(0x1234));
+}
+
+#[test]
+fn syscall_diff() {
+    let proc = common::spawn_at_entry();
+    let before = proc.registers().unwrap();
+    let after = proc.syscall_regs(Sysno::getpid, 0, 0, 0, 0, 0, 0).unwrap();
+
+    let changed: Vec<&str> = before.diff(&after).iter().map(|c| c.name).collect();
+    // return value, and the rip and rflags saved by the syscall instruction
+    assert!(changed.contains(&"rax"));
+    assert!(changed.contains(&"rcx"));
+    assert!(changed.contains(&"rip"));
+    assert_eq!(after.get("rax"), Some(proc.pid().as_raw() as u64));
+    assert_eq!(proc.registers().unwrap(), before);
+
+    let dump = before.to_string();
+    assert!(dump.starts_with("rax "));
+    assert!(dump.contains("eflags"));
+}
+
+#[test]
+fn fp_registers() {
+    let proc = common::spawn_at_entry();
+    let mut fp = proc.fp_registers().unwrap();
+    // initial state set up by the kernel at exec
+    assert_eq!(fp.fcw(), 0x37F);
+    assert_eq!(fp.mxcsr() & 0xFFC0, 0x1F80);
+
+    let value = (2.5f64.to_bits() as u128) << 64 | (-1.0f64).to_bits() as u128;
+    fp.set_xmm(3, value).unwrap();
+    assert!(matches!(
+        fp.set_xmm(16, value),
+        Err(HostError::NoSuchRegister(name)) if name == "xmm16"
+    ));
+    proc.set_fp_registers(&fp).unwrap();
+
+    let fp = proc.fp_registers().unwrap();
+    assert_eq!(fp.xmm(3), Some(value));
+    assert_eq!(fp.xmm_f64(3), Some([-1.0, 2.5]));
+    assert_eq!(fp.xmm(16), None);
+    assert_eq!(fp.st(8), None);
+    assert_eq!(fp.to_string().matches("xmm").count(), 16);
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::Victim;
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Run the debugger with `args`, feed it `script` and return what it printed.
+fn debug(args: &[&str], script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_host"))
+        .arg("debug")
+        .args(args)
+        .env("HOME", std::env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn break_step_examine() {
+    let victim = Victim::start(&["--interval", "20"]);
+    let pid = victim.pid.to_string();
+    let out = debug(
+        &["--pid", &pid],
+        "break victim_tick\n\
+         continue\n\
+         backtrace\n\
+         x/2xg VICTIM_MAGIC\n\
+         stepi\n\
+         \n\
+         set reg rax 0x42\n\
+         regs rax\n\
+         info breakpoints\n\
+         delete 1\n\
+         info breakpoints\n\
+         quit\n",
+    );
+
+    assert!(out.contains("Breakpoint 1, "), "{}", out);
+    assert!(out.contains("#0  "), "{}", out);
+    assert!(out.contains("victim!victim::main+"), "{}", out);
+    assert!(out.contains("0xdeadbeefcafef00d"), "{}", out);
+    // stepi and the empty line repeating it
+    let steps = out
+        .lines()
+        .filter(|line| line.starts_with("0x") && line.contains("in victim!victim_tick+0x"));
+    assert_eq!(steps.count(), 2, "{}", out);
+    assert!(out.contains("rax            0x42"), "{}", out);
+    assert!(out.contains("No breakpoints"), "{}", out);
+
+    let state = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+    assert!(!state.contains(") t "), "{}", state);
+}
+
+#[test]
+fn step_over_calls() {
+    let victim = Victim::start(&["--interval", "20"]);
+    let pid = victim.pid.to_string();
+    let out = debug(
+        &["--pid", &pid],
+        "break victim_tick\ncontinue\ndelete\nstep 100\nquit\n",
+    );
+    // the ret of victim_tick leads back to main, the calls in between are stepped over
+    let last = out.lines().last().unwrap();
+    assert!(last.contains("victim!victim::main+"), "{}", out);
+}
+
+#[test]
+fn spawn_until_exit() {
+    let victim = common::victim();
+    let out = debug(
+        &[
+            "--",
+            victim.to_str().unwrap(),
+            "--ticks",
+            "2",
+            "--interval",
+            "10",
+            "--quiet",
+        ],
+        "continue\nregs\n",
+    );
+    assert!(out.contains("exited: Exited(0)"), "{}", out);
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::Victim;
+use host::{Pattern, ScanFilter, UProc};
+use std::time::Duration;
+
+#[test]
+fn find_pattern_across_chunks() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let mem = common::alloc(&proc, 3 * 4096);
+    mem.write_bytes(4094, &[0xde, 0xad, 0x42, 0xef]).unwrap();
+    mem.write_bytes(8000, &[0xde, 0xad, 0x17, 0xef]).unwrap();
+
+    let scanner = proc
+        .scanner()
+        .range(mem.addr, mem.addr + mem.len)
+        .chunk_size(4096);
+    let pattern: Pattern = "de ad ?? ef".parse().unwrap();
+    assert_eq!(
+        scanner.find_pattern(&pattern).unwrap(),
+        [mem.addr + 4094, mem.addr + 8000]
+    );
+    assert_eq!(
+        scanner.find_bytes(&[0xde, 0xad, 0x17]).unwrap(),
+        [mem.addr + 8000]
+    );
+}
+
+#[test]
+fn find_string_in_binary() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let victim = common::victim();
+    let hits = proc
+        .scanner()
+        .path(victim.to_str().unwrap())
+        .find_str("Hello, world!")
+        .unwrap();
+    assert!(!hits.is_empty());
+    assert_eq!(proc.mem_read(hits[0], 13).unwrap(), b"Hello, world!");
+}
+
+#[test]
+fn rescan_narrows_hits() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let mem = common::alloc(&proc, 2 * 4096);
+    for offset in [0u64, 64, 4096, 6001] {
+        mem.write_value(offset, &0x5eed_u64).unwrap();
+    }
+
+    let scanner = proc
+        .scanner()
+        .writable()
+        .align(8)
+        .range(mem.addr, mem.addr + mem.len);
+    let mut scan = scanner.find_value(0x5eed_u64).unwrap();
+    assert_eq!(scan.hits().len(), 3, "6001 is not 8 byte aligned");
+
+    mem.write_value(64, &0x5eee_u64).unwrap();
+    mem.write_value(4096, &0x5eec_u64).unwrap();
+    let mut increased = scan.clone();
+    assert_eq!(increased.rescan(ScanFilter::Increased).unwrap(), 1);
+    assert_eq!(increased.addrs(), [mem.addr + 64]);
+    assert_eq!(increased.hits()[0].value, 0x5eee);
+
+    assert_eq!(scan.rescan(ScanFilter::Changed).unwrap(), 2);
+    assert_eq!(scan.rescan(ScanFilter::Unchanged).unwrap(), 2);
+    assert_eq!(scan.rescan(ScanFilter::Equal(0x5eec)).unwrap(), 1);
+    assert_eq!(scan.addrs(), [mem.addr + 4096]);
+}
+
+#[test]
+fn rescan_follows_heap_value() {
+    let victim = Victim::start(&["--interval", "20"]);
+    let heap_value = victim.addr("heap_value");
+    let proc = UProc::seize(victim.pid).unwrap();
+
+    let value = proc.read_value::<u64>(heap_value).unwrap();
+    let mut scan = proc.scanner().writable().find_value(value).unwrap();
+    assert!(scan.addrs().contains(&heap_value));
+
+    proc.resume().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    proc.interrupt().unwrap();
+
+    scan.rescan(ScanFilter::Increased).unwrap();
+    let hit = scan
+        .hits()
+        .iter()
+        .find(|hit| hit.addr == heap_value)
+        .unwrap();
+    assert!(hit.value > value);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::Victim;
+use host::UProc;
+use nix::unistd::Pid;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    time::Duration,
+};
+
+/// Scheduler state from `/proc/pid/stat`: `t` in a ptrace stop, `T` in a job control stop.
+fn state(pid: Pid) -> char {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+    let (_, rest) = stat.rsplit_once(") ").unwrap();
+    rest.chars().next().unwrap()
+}
+
+/// Whether any signal is pending for the process or its leader thread.
+fn signals_pending(pid: Pid) -> bool {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).unwrap();
+    status
+        .lines()
+        .filter(|l| l.starts_with("SigPnd:") || l.starts_with("ShdPnd:"))
+        .any(|l| u64::from_str_radix(l.split_whitespace().nth(1).unwrap(), 16).unwrap() != 0)
+}
+
+fn assert_running(pid: Pid) {
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(matches!(state(pid), 'S' | 'R'), "state {}", state(pid));
+    assert!(!signals_pending(pid));
+}
+
+#[test]
+fn seize_interrupt_resume() {
+    let victim = Victim::start(&[]);
+    let pid = victim.pid;
+
+    let proc = UProc::seize(pid).unwrap();
+    assert_eq!(state(pid), 't');
+    assert!(!signals_pending(pid));
+    assert!(proc.registers().is_ok());
+
+    proc.resume().unwrap();
+    assert_running(pid);
+
+    proc.interrupt().unwrap();
+    assert_eq!(state(pid), 't');
+    assert!(!signals_pending(pid));
+    assert!(proc.registers().is_ok());
+
+    drop(proc);
+    assert_running(pid);
+}
+
+#[test]
+fn attach_detach_leaves_no_sigstop() {
+    let victim = Victim::start(&[]);
+    let pid = victim.pid;
+
+    let proc = UProc::attach(pid).unwrap();
+    proc.resume().unwrap();
+    proc.interrupt().unwrap();
+    drop(proc);
+
+    assert_running(pid);
+}
+
+#[test]
+fn panic_detaches_and_restores() {
+    let victim = Victim::start(&[]);
+    let pid = victim.pid;
+
+    let mut patched = None;
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let proc = UProc::seize(pid).unwrap();
+        let sleep = proc.symbols().unwrap().resolve("clock_nanosleep").unwrap();
+        patched = Some((sleep, proc.mem_read(sleep, 1).unwrap()));
+        proc.set_breakpoint(sleep).unwrap();
+        proc.resume().unwrap();
+        panic!("tracer bug");
+    }));
+    assert!(res.is_err());
+
+    assert_running(pid);
+    // the int3 is gone, the victim would die of SIGTRAP on its next sleep otherwise
+    let (sleep, orig) = patched.unwrap();
+    assert_eq!(read_byte(pid, sleep), orig[0]);
+}
+
+fn read_byte(pid: Pid, addr: u64) -> u8 {
+    use std::os::unix::fs::FileExt;
+    let mem = std::fs::File::open(format!("/proc/{}/mem", pid)).unwrap();
+    let mut byte = [0u8];
+    mem.read_exact_at(&mut byte, addr).unwrap();
+    byte[0]
+}
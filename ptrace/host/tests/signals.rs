@@ -0,0 +1,102 @@
+mod common;
+
+use host::{Halt, HostError, SignalPolicy, Stop, StopReason};
+use nix::sys::signal::{
+    kill,
+    Signal::{SIGSTOP, SIGUSR1},
+};
+use syscalls::Sysno;
+
+#[test]
+fn signal_during_injection_is_delivered() {
+    let proc = common::spawn_at_entry();
+    kill(proc.pid(), SIGUSR1).unwrap();
+
+    // the signal stops the single step first, it is kept for later
+    let pid = proc.syscall(Sysno::getpid, 0, 0, 0, 0, 0, 0).unwrap();
+    assert_eq!(pid, proc.pid().as_raw() as u64);
+
+    match proc.continue_until_breakpoint() {
+        Err(HostError::ProcessExited(Stop {
+            reason: StopReason::Killed(SIGUSR1),
+            ..
+        })) => {}
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
+#[test]
+fn suppressed_signal_is_dropped() {
+    let proc = common::spawn_at_entry();
+    proc.set_signal_policy(SIGUSR1, SignalPolicy::Suppress);
+    assert_eq!(proc.signal_policy(SIGUSR1), SignalPolicy::Suppress);
+    kill(proc.pid(), SIGUSR1).unwrap();
+
+    let getpid = proc.symbols().unwrap().resolve("getpid").unwrap();
+    let pid = proc.call_function(getpid, &[]).unwrap();
+    assert_eq!(pid, proc.pid().as_raw() as u64);
+
+    let sleep = proc.symbols().unwrap().resolve("clock_nanosleep").unwrap();
+    proc.set_breakpoint(sleep).unwrap();
+    assert_eq!(proc.continue_until_breakpoint().unwrap(), sleep);
+}
+
+#[test]
+fn group_stop() {
+    let proc = common::spawn_at_entry();
+    assert_eq!(proc.signal_policy(SIGSTOP), SignalPolicy::Suppress);
+    proc.set_signal_policy(SIGSTOP, SignalPolicy::Deliver);
+    kill(proc.pid(), SIGSTOP).unwrap();
+
+    proc.resume_all().unwrap();
+    let stop = proc.wait_any().unwrap();
+    assert_eq!(stop.reason, StopReason::Signal(SIGSTOP));
+
+    // delivering it puts the process in a group-stop
+    proc.resume_all().unwrap();
+    let stop = proc.wait_any().unwrap();
+    assert_eq!(stop.reason, StopReason::GroupStop(SIGSTOP));
+}
+
+#[test]
+fn handled_signal_reaches_victim() {
+    let proc = common::spawn_victim(&["--handle", "USR1", "--interval", "10", "--quiet"]);
+    let symbols = proc.symbols().unwrap();
+    let tick = symbols.resolve("victim_tick").unwrap();
+    let caught = symbols.resolve("VICTIM_SIGNALS").unwrap();
+
+    // the handler is installed before the first tick
+    proc.set_breakpoint(tick).unwrap();
+    proc.continue_until_breakpoint().unwrap();
+    kill(proc.pid(), SIGUSR1).unwrap();
+    proc.continue_until_breakpoint().unwrap();
+
+    assert_eq!(proc.read_value::<u64>(caught).unwrap(), 1);
+}
+
+#[test]
+fn cont_stops_on_signal() {
+    let proc = common::spawn_victim(&["--handle", "USR1", "--interval", "10", "--quiet"]);
+    let symbols = proc.symbols().unwrap();
+    let tick = symbols.resolve("victim_tick").unwrap();
+    let caught = symbols.resolve("VICTIM_SIGNALS").unwrap();
+
+    proc.set_breakpoint(tick).unwrap();
+    assert!(matches!(proc.cont().unwrap(), Halt::Breakpoint { addr, .. } if addr == tick));
+    proc.remove_breakpoint(tick).unwrap();
+
+    kill(proc.pid(), SIGUSR1).unwrap();
+    assert_eq!(
+        proc.cont().unwrap(),
+        Halt::Signal {
+            tid: proc.pid(),
+            signal: SIGUSR1
+        }
+    );
+    assert_eq!(proc.read_value::<u64>(caught).unwrap(), 0);
+
+    // delivered on the next resume
+    proc.set_breakpoint(tick).unwrap();
+    proc.continue_until_breakpoint().unwrap();
+    assert_eq!(proc.read_value::<u64>(caught).unwrap(), 1);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use host::UProc;
+use nix::{sys::signal, unistd::Pid};
+
+fn proc_state(pid: Pid) -> char {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+    let (_, rest) = stat.rsplit_once(')').unwrap();
+    rest.trim_start().chars().next().unwrap()
+}
+
+#[test]
+fn spawn_stops_at_exec() {
+    let victim = common::victim();
+    let proc = UProc::spawn(&victim, [] as [&str; 0], [("RUST_LOG", "off")]).unwrap();
+
+    assert_eq!(proc_state(proc.pid()), 't');
+    let exe = std::fs::read_link(format!("/proc/{}/exe", proc.pid())).unwrap();
+    assert_eq!(exe, victim);
+}
+
+#[test]
+fn spawned_is_killed_on_drop() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let pid = proc.pid();
+    drop(proc);
+
+    assert_eq!(signal::kill(pid, None), Err(nix::errno::Errno::ESRCH));
+}
+
+#[test]
+fn spawn_missing_binary_fails() {
+    assert!(UProc::spawn(
+        "/nonexistent/victim",
+        [] as [&str; 0],
+        [] as [(&str, &str); 0]
+    )
+    .is_err());
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use host::UProc;
+use nix::sys::ptrace;
+
+#[test]
+fn resolve_victim_main() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let symbols = proc.symbols().unwrap();
+
+    let victim = symbols.module("victim").unwrap();
+    assert_ne!(victim.bias, 0, "victim is built as a PIE");
+
+    let main = symbols.resolve("victim::main").unwrap();
+    assert!(victim.contains(main));
+
+    let location = symbols.symbolize(main + 1).unwrap();
+    assert_eq!(location.module.name(), "victim");
+    assert_eq!(location.symbol.unwrap().name, "victim::main");
+    assert_eq!(location.offset, 1);
+    assert_eq!(location.to_string(), "victim!victim::main+0x1");
+
+    assert_eq!(symbols.resolve("no_such_symbol_anywhere"), None);
+}
+
+#[test]
+fn break_on_libc_function() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+
+    // libc is only mapped once the dynamic loader ran
+    common::run_to_entry(&proc);
+
+    let symbols = proc.symbols().unwrap();
+    let libc = symbols
+        .modules()
+        .iter()
+        .find(|m| m.name().starts_with("libc.so"))
+        .unwrap();
+    let sleep = libc.resolve("clock_nanosleep").unwrap().addr;
+    proc.set_breakpoint(sleep).unwrap();
+
+    // the second hit steps over the first one
+    for _ in 0..2 {
+        assert_eq!(proc.continue_until_breakpoint().unwrap(), sleep);
+        let rip = ptrace::getregs(proc.current_thread()).unwrap().rip;
+        let location = symbols.symbolize(rip).unwrap();
+        assert_eq!(location.offset, 0);
+        assert!(location.symbol.unwrap().name.contains("clock_nanosleep"));
+    }
+}
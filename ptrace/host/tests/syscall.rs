@@ -0,0 +1,105 @@
+mod common;
+
+use host::{HostError, SyscallResult, UProc};
+use nix::{
+    errno::Errno,
+    libc::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE},
+};
+use std::time::Duration;
+use syscalls::Sysno;
+
+/// Victim stopped in the middle of the sleep of its main loop.
+fn spawn_sleeping() -> UProc {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    proc.resume().unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    proc.interrupt().unwrap();
+
+    let regs = proc.registers().unwrap();
+    assert_eq!(
+        regs.interrupted_syscall(),
+        Some(Sysno::clock_nanosleep as u64)
+    );
+    proc
+}
+
+/// The sleep goes on where it was interrupted, through `restart_syscall`.
+fn assert_sleep_restarts(proc: &UProc) {
+    let event = proc.syscalls().unwrap().next().unwrap().unwrap();
+    assert_eq!(event.sysno, Some(Sysno::restart_syscall));
+    assert_eq!(event.ret, Some(SyscallResult(0)));
+}
+
+#[test]
+fn syscall_value() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let pid = proc.syscall(Sysno::getpid, 0, 0, 0, 0, 0, 0).unwrap();
+    assert_eq!(pid, proc.pid().as_raw() as u64);
+}
+
+#[test]
+fn syscall_errno() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+
+    let err = proc
+        .syscall(Sysno::close, u64::MAX, 0, 0, 0, 0, 0)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        HostError::Syscall {
+            sysno: Sysno::close,
+            errno: Errno::EBADF
+        }
+    ));
+
+    // the raw registers still carry the negative return
+    let regs = proc
+        .syscall_regs(Sysno::close, u64::MAX, 0, 0, 0, 0, 0)
+        .unwrap();
+    assert_eq!(regs.get("rax").unwrap() as i64, -(Errno::EBADF as i64));
+}
+
+#[test]
+fn malloc_errno() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+    let res = proc.malloc(
+        0,
+        0,
+        (PROT_READ | PROT_WRITE) as u64,
+        (MAP_PRIVATE | MAP_ANONYMOUS) as u64,
+        u64::MAX,
+        0,
+    );
+    assert!(matches!(
+        res,
+        Err(HostError::Syscall {
+            sysno: Sysno::mmap,
+            errno: Errno::EINVAL
+        })
+    ));
+}
+
+#[test]
+fn syscall_in_interrupted_sleep() {
+    let proc = spawn_sleeping();
+    let before = proc.registers().unwrap();
+
+    let pid = proc.syscall(Sysno::getpid, 0, 0, 0, 0, 0, 0).unwrap();
+    assert_eq!(pid, proc.pid().as_raw() as u64);
+    assert_eq!(proc.registers().unwrap(), before);
+
+    assert_sleep_restarts(&proc);
+}
+
+#[test]
+fn call_in_interrupted_sleep() {
+    let proc = spawn_sleeping();
+    let before = proc.registers().unwrap();
+
+    let getpid = proc.symbols().unwrap().resolve("getpid").unwrap();
+    let pid = proc.call_function(getpid, &[]).unwrap();
+    assert_eq!(pid, proc.pid().as_raw() as u64);
+    assert_eq!(proc.registers().unwrap(), before);
+
+    assert_sleep_restarts(&proc);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::Victim;
+use host::{HostError, StopReason, UProc};
+use nix::unistd::Pid;
+
+fn tasks(pid: Pid) -> Vec<Pid> {
+    let mut tids: Vec<Pid> = std::fs::read_dir(format!("/proc/{}/task", pid))
+        .unwrap()
+        .map(|e| Pid::from_raw(e.unwrap().file_name().to_str().unwrap().parse().unwrap()))
+        .collect();
+    tids.sort();
+    tids
+}
+
+#[test]
+fn follow_new_threads() {
+    let proc = common::spawn_victim(&["--threads", "3", "--thread-delay", "50", "--quiet"]);
+    assert_eq!(proc.threads(), [proc.pid()]);
+
+    let mut new_threads = 0;
+    proc.resume_all().unwrap();
+    while new_threads < 3 {
+        let stop = proc.wait_any().unwrap();
+        if let StopReason::NewThread(child) = stop.reason {
+            new_threads += 1;
+            assert!(proc.threads().contains(&child));
+        }
+        proc.resume_all().unwrap();
+    }
+    proc.stop_all().unwrap();
+
+    assert_eq!(proc.threads().len(), 4);
+    assert_eq!(proc.threads(), tasks(proc.pid()));
+}
+
+#[test]
+fn attach_all_threads() {
+    let victim = Victim::start(&["--threads", "3"]);
+    let pid = victim.pid;
+
+    let proc = UProc::attach(pid).unwrap();
+    assert_eq!(proc.threads().len(), 4);
+    assert_eq!(proc.threads(), tasks(pid));
+    assert_eq!(proc.current_thread(), pid);
+
+    let other = proc.threads()[1];
+    proc.select_thread(other).unwrap();
+    assert_eq!(proc.current_thread(), other);
+    assert!(matches!(
+        proc.select_thread(Pid::from_raw(1)),
+        Err(HostError::NoSuchThread(_))
+    ));
+}
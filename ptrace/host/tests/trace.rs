@@ -0,0 +1,52 @@
+mod common;
+
+use host::{SyscallArg, SyscallResult, UProc};
+use std::ops::ControlFlow;
+use syscalls::Sysno;
+
+#[test]
+fn trace_victim_loop() {
+    let proc = UProc::spawn(common::victim(), [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+
+    let mut slept = false;
+    let mut logged = false;
+    proc.trace_syscalls(|event| {
+        match event.sysno {
+            Some(Sysno::clock_nanosleep) => {
+                assert_eq!(event.ret, Some(SyscallResult(0)));
+                slept = true;
+            }
+            Some(Sysno::write) if slept && event.args[0] == SyscallArg::Fd(2) => {
+                assert!(event.to_string().contains("Hello, world!"));
+                logged = true;
+            }
+            _ => {}
+        }
+        if logged {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })
+    .unwrap();
+
+    assert!(slept && logged);
+}
+
+#[test]
+fn syscalls_end_on_exit() {
+    let proc = UProc::spawn("/bin/true", [] as [&str; 0], [] as [(&str, &str); 0]).unwrap();
+
+    let events = proc
+        .syscalls()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let last = events.last().unwrap();
+    assert_eq!(last.sysno, Some(Sysno::exit_group));
+    assert_eq!(last.ret, None);
+    assert!(
+        events.iter().any(|e| e.sysno == Some(Sysno::execve))
+            || events.iter().any(|e| e.sysno == Some(Sysno::brk))
+    );
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use host::{Halt, HostError, UProc, WatchKind, WATCHPOINT_SLOTS};
+
+#[test]
+fn watch_counter_write() {
+    let victim = common::Victim::start(&["--interval", "10"]);
+    let counter = victim.addr("counter");
+    let proc = UProc::attach(victim.pid).unwrap();
+    let before = proc.read_value::<u64>(counter).unwrap();
+
+    let slot = proc.set_watchpoint(counter, 8, WatchKind::Write).unwrap();
+    let watchpoint = match proc.cont().unwrap() {
+        Halt::Watchpoint { tid, watchpoint } => {
+            assert_eq!(tid, victim.pid);
+            watchpoint
+        }
+        halt => panic!("{:?}", halt),
+    };
+    assert_eq!(watchpoint.slot, slot);
+    assert_eq!(watchpoint.addr, counter);
+    assert_eq!(proc.read_value::<u64>(counter).unwrap(), before + 1);
+
+    // stopped right after the increment, under the function doing it
+    let frames = proc.backtrace(victim.pid).unwrap();
+    assert!(frames
+        .iter()
+        .take(3)
+        .any(|frame| frame.function.as_deref() == Some("victim_tick")));
+
+    // hit again on the next tick
+    assert!(matches!(proc.cont().unwrap(), Halt::Watchpoint { .. }));
+    assert_eq!(proc.read_value::<u64>(counter).unwrap(), before + 2);
+
+    proc.remove_watchpoint(slot).unwrap();
+    assert!(proc.watchpoints().is_empty());
+}
+
+#[test]
+fn watch_heap_read_write() {
+    let victim = common::Victim::start(&["--interval", "10"]);
+    let heap_value = victim.addr("heap_value");
+    let proc = UProc::attach(victim.pid).unwrap();
+
+    proc.set_watchpoint(heap_value, 4, WatchKind::ReadWrite)
+        .unwrap();
+    match proc.cont().unwrap() {
+        Halt::Watchpoint { watchpoint, .. } => {
+            assert_eq!(watchpoint.addr, heap_value);
+            assert_eq!(watchpoint.len, 4);
+            assert_eq!(watchpoint.kind, WatchKind::ReadWrite);
+        }
+        halt => panic!("{:?}", halt),
+    }
+}
+
+#[test]
+fn detach_clears_watchpoints() {
+    let victim = common::Victim::start(&["--interval", "5"]);
+    let counter = victim.addr("counter");
+    {
+        let proc = UProc::attach(victim.pid).unwrap();
+        proc.set_watchpoint(counter, 8, WatchKind::Write).unwrap();
+    }
+
+    // without the debugger a watchpoint left behind kills it with SIGTRAP
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", victim.pid)).unwrap();
+    let state = stat.rsplit(')').next().unwrap().split_whitespace().next();
+    assert_ne!(state, Some("Z"));
+}
+
+#[test]
+fn bad_watchpoints() {
+    let proc = common::spawn_victim(&[]);
+    assert!(matches!(
+        proc.set_watchpoint(0x1001, 4, WatchKind::Write),
+        Err(HostError::BadWatchpoint {
+            addr: 0x1001,
+            len: 4
+        })
+    ));
+    assert!(matches!(
+        proc.set_watchpoint(0x1000, 3, WatchKind::Write),
+        Err(HostError::BadWatchpoint { len: 3, .. })
+    ));
+    assert!(matches!(
+        proc.remove_watchpoint(0),
+        Err(HostError::NoWatchpoint(0))
+    ));
+
+    for i in 0..WATCHPOINT_SLOTS {
+        let slot = proc
+            .set_watchpoint(0x1000 + 8 * i as u64, 8, WatchKind::Write)
+            .unwrap();
+        assert_eq!(slot, i);
+    }
+    assert!(matches!(
+        proc.set_watchpoint(0x2000, 8, WatchKind::Write),
+        Err(HostError::NoFreeWatchpoint)
+    ));
+
+    // a freed debug register is used again
+    proc.remove_watchpoint(1).unwrap();
+    assert_eq!(
+        proc.set_watchpoint(0x2000, 1, WatchKind::ReadWrite)
+            .unwrap(),
+        1
+    );
+    assert_eq!(proc.watchpoints().len(), WATCHPOINT_SLOTS);
+}
@@ -1,11 +1,294 @@
+use clap::{Parser, ValueEnum};
+use std::{
+    hint::black_box,
+    io::Write,
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::Command,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// First value of the heap counter, it grows by one on every tick.
+const HEAP_BASE: u64 = 0x1337_0000_0000;
+
+/// Constant found by memory scans.
+#[no_mangle]
+pub static VICTIM_MAGIC: u64 = 0xDEAD_BEEF_CAFE_F00D;
+
+/// Ticks of the main loop so far.
+#[no_mangle]
+pub static VICTIM_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Signals caught by the handlers installed with `--handle`.
+#[no_mangle]
+pub static VICTIM_SIGNALS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of the last signal caught.
+#[no_mangle]
+pub static VICTIM_LAST_SIGNAL: AtomicU64 = AtomicU64::new(0);
+
+/// Target of injected calls, all arguments in registers on x86_64.
+#[no_mangle]
+pub extern "C" fn victim_add(a: u64, b: u64) -> u64 {
+    a.wrapping_add(b)
+}
+
+/// Target of injected calls, the last two arguments on the stack on x86_64.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn victim_sum8(
+    a: u64,
+    b: u64,
+    c: u64,
+    d: u64,
+    e: u64,
+    f: u64,
+    g: u64,
+    h: u64,
+) -> u64 {
+    [b, c, d, e, f, g, h]
+        .into_iter()
+        .fold(a, |sum, x| sum.wrapping_add(x))
+}
+
+/// Called once per tick of the main loop, a place for breakpoints.
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn victim_tick() -> u64 {
+    VICTIM_COUNTER.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+/// Body of the worker threads, never returns.
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn victim_worker(id: u64, interval_ms: u64, spin: bool) {
+    log::debug!("worker {} started", id);
+    loop {
+        if spin {
+            spin_for(Duration::from_millis(interval_ms));
+        } else {
+            std::thread::sleep(Duration::from_millis(interval_ms));
+        }
+    }
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    VICTIM_SIGNALS.fetch_add(1, Ordering::SeqCst);
+    VICTIM_LAST_SIGNAL.store(signal as u64, Ordering::SeqCst);
+}
+
+/// How the main loop waits between ticks, so that tracers find it blocked in a known place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Block {
+    /// `std::thread::sleep`, which is `clock_nanosleep`
+    Sleep,
+    /// `nanosleep`
+    Nanosleep,
+    /// `read` from a pipe a helper thread writes to once per interval
+    Read,
+    /// `pause` until a signal arrives, one tick per signal
+    Pause,
+    /// busy loop without syscalls
+    Spin,
+}
+
+/// Configurable target for the host tests. Without flags it logs "Hello, world!" every 3
+/// seconds.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Worker threads to start
+    #[arg(long, default_value_t = 0)]
+    threads: u64,
+    /// Delay before starting each worker thread, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    thread_delay: u64,
+    /// How the main loop waits between ticks
+    #[arg(long, value_enum, default_value_t = Block::Sleep)]
+    block: Block,
+    /// Time between ticks, in milliseconds
+    #[arg(long, default_value_t = 3000)]
+    interval: u64,
+    /// Exit after this many ticks
+    #[arg(long)]
+    ticks: Option<u64>,
+    /// Fork before the first tick, the child ticks too and is killed with the parent
+    #[arg(long)]
+    fork: bool,
+    /// Run this program and wait for it before the first tick
+    #[arg(long)]
+    spawn: Option<PathBuf>,
+    /// Execute this program instead of exiting after the last tick
+    #[arg(long)]
+    exec: Option<PathBuf>,
+    /// Count a signal in `VICTIM_SIGNALS`, by name like `USR1` or by number
+    #[arg(long = "handle", value_parser = parse_signal)]
+    handle: Vec<libc::c_int>,
+    /// Print the addresses of the known variables and functions, then `ready`
+    #[arg(long)]
+    print_addrs: bool,
+    /// Only log errors
+    #[arg(long)]
+    quiet: bool,
+}
+
+fn parse_signal(s: &str) -> Result<libc::c_int, String> {
+    if let Ok(signal) = s.parse() {
+        return Ok(signal);
+    }
+    let signal = match s.trim_start_matches("SIG") {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "PIPE" => libc::SIGPIPE,
+        "ALRM" => libc::SIGALRM,
+        "TERM" => libc::SIGTERM,
+        "CHLD" => libc::SIGCHLD,
+        "CONT" => libc::SIGCONT,
+        "WINCH" => libc::SIGWINCH,
+        _ => return Err(format!("unknown signal `{}`", s)),
+    };
+    Ok(signal)
+}
+
+fn handle_signal(signal: libc::c_int) {
+    // SAFETY: the handler only touches atomics, sigaction is plain data
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+            panic!("sigaction({}): {}", signal, std::io::Error::last_os_error());
+        }
+    }
+}
+
+fn spin_for(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        black_box(VICTIM_MAGIC);
+    }
+}
+
+fn nanosleep(duration: Duration) {
+    let mut req = libc::timespec {
+        tv_sec: duration.as_secs() as libc::time_t,
+        tv_nsec: duration.subsec_nanos() as libc::c_long,
+    };
+    let mut rem = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: both timespecs are valid for the call
+    while unsafe { libc::nanosleep(&req, &mut rem) } != 0 {
+        req = rem;
+    }
+}
+
+/// Read end of a pipe written once per `interval` by a helper thread.
+fn ticking_pipe(interval: Duration) -> libc::c_int {
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for both ends
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        panic!("pipe: {}", std::io::Error::last_os_error());
+    }
+    let write_fd = fds[1];
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        // SAFETY: writes one byte from a live buffer
+        unsafe { libc::write(write_fd, [0u8].as_ptr().cast(), 1) };
+    });
+    fds[0]
+}
+
 fn main() {
+    let args = Args::parse();
+    let level = if args.quiet {
+        log::LevelFilter::Error
+    } else {
+        log::LevelFilter::Trace
+    };
     pretty_env_logger::formatted_builder()
-        .filter_level(log::LevelFilter::Trace)
+        .filter_level(level)
         .init();
 
     log::info!("My Pid is {}", std::process::id());
+    let interval = Duration::from_millis(args.interval);
+
+    for &signal in &args.handle {
+        handle_signal(signal);
+    }
+    for id in 0..args.threads {
+        std::thread::sleep(Duration::from_millis(args.thread_delay));
+        let spin = args.block == Block::Spin;
+        let interval = args.interval;
+        std::thread::spawn(move || victim_worker(id, interval, spin));
+    }
+
+    let heap_value = Box::new(AtomicU64::new(HEAP_BASE));
+    let heap_string = String::from("victim heap string");
+    if args.print_addrs {
+        let addrs: [(&str, usize); 9] = [
+            ("magic", &VICTIM_MAGIC as *const u64 as usize),
+            ("counter", VICTIM_COUNTER.as_ptr() as usize),
+            ("signals", VICTIM_SIGNALS.as_ptr() as usize),
+            ("heap_value", heap_value.as_ptr() as usize),
+            ("heap_string", heap_string.as_ptr() as usize),
+            ("victim_add", victim_add as *const () as usize),
+            ("victim_sum8", victim_sum8 as *const () as usize),
+            ("victim_tick", victim_tick as *const () as usize),
+            ("victim_worker", victim_worker as *const () as usize),
+        ];
+        let mut out = std::io::stdout().lock();
+        for (name, addr) in addrs {
+            writeln!(out, "{} {:#x}", name, addr).unwrap();
+        }
+        writeln!(out, "ready").unwrap();
+        out.flush().unwrap();
+    }
+
+    if let Some(program) = &args.spawn {
+        let status = Command::new(program).status().unwrap();
+        log::info!("{} exited with {}", program.display(), status);
+    }
+    // SAFETY: the child only runs the main loop, which needs none of the threads left behind
+    if args.fork && unsafe { libc::fork() } == 0 {
+        // SAFETY: prctl takes no pointers here
+        unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) };
+        log::info!("forked child pid {}", std::process::id());
+    }
+
+    let pipe = (args.block == Block::Read).then(|| ticking_pipe(interval));
     loop {
-        std::thread::sleep(std::time::Duration::from_millis(3000));
+        match args.block {
+            Block::Sleep => std::thread::sleep(interval),
+            Block::Nanosleep => nanosleep(interval),
+            Block::Read => {
+                let mut byte = 0u8;
+                // SAFETY: reads one byte into a live buffer
+                unsafe { libc::read(pipe.unwrap(), (&mut byte as *mut u8).cast(), 1) };
+            }
+            // SAFETY: pause takes no arguments
+            Block::Pause => unsafe {
+                libc::pause();
+            },
+            Block::Spin => spin_for(interval),
+        }
+
+        let tick = victim_tick();
+        heap_value.fetch_add(1, Ordering::SeqCst);
         log::info!("Hello, world!");
+        if args.ticks.is_some_and(|ticks| tick >= ticks) {
+            break;
+        }
+    }
+    black_box(&heap_string);
+
+    if let Some(program) = &args.exec {
+        let err = Command::new(program).exec();
+        panic!("exec {}: {}", program.display(), err);
     }
-}
\ No newline at end of file
+}